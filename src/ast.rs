@@ -1,4 +1,5 @@
 pub mod expr;
+pub mod optimizer;
 pub mod resolver;
 pub mod stmt;
 pub mod value;
@@ -1,7 +1,9 @@
 //! expressions in AST
 use crate::ast::Resolver;
 use crate::error::Error;
+use crate::vm::Compile;
 use crate::Value;
+use std::rc::Rc;
 
 pub mod literal;
 pub use literal::Literal;
@@ -18,10 +20,14 @@ pub mod assignment;
 pub use assignment::Assignment;
 pub mod logic;
 pub use logic::Logic;
+pub mod conditional;
+pub use conditional::Conditional;
 pub mod call;
 pub use call::Call;
 pub mod get;
 pub use get::Get;
+pub mod optional_chain;
+pub use optional_chain::OptionalChain;
 pub mod set;
 pub use set::Set;
 pub mod this;
@@ -34,9 +40,21 @@ pub mod array;
 pub use array::Array;
 pub mod array_assignment;
 pub use array_assignment::ArrayAssignment;
+pub mod interpolation;
+pub use interpolation::Interpolation;
+pub mod lambda;
+pub use lambda::Lambda;
 
-pub trait Expr: std::fmt::Display + std::fmt::Debug + Resolver {
-    fn eval(&self) -> Result<Box<Value>, Error>;
+pub trait Expr: std::fmt::Display + std::fmt::Debug + Resolver + Compile {
+    fn eval(&self) -> Result<Value, Error>;
+    /// The node with its constant parts folded, `None` to keep this one
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        None
+    }
+    /// The call this expression is, for the resolver to find tail calls
+    fn as_call(&self) -> Option<&Call> {
+        None
+    }
     fn type_name(&self) -> String {
         std::any::type_name::<Self>().to_string()
     }
@@ -1,4 +1,6 @@
+use crate::ast::optimizer::fold_all;
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler, OpCode};
 use std::rc::Rc;
 
 #[derive(Debug)]
@@ -30,11 +32,28 @@ impl Resolver for Array {
 }
 
 impl Expr for Array {
-    fn eval(&self) -> Result<Box<crate::ast::Value>, crate::error::Error> {
+    fn eval(&self) -> Result<crate::ast::Value, crate::error::Error> {
         let mut values = Vec::new();
         for value in &self.values {
             values.push(value.eval()?);
         }
-        Ok(Box::new(crate::ast::Value::Array(values)))
+        Ok(crate::ast::Value::array(values))
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        fold_all(&self.values).map(|values| Rc::new(Array { values }) as Rc<dyn Expr>)
+    }
+}
+
+impl Compile for Array {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), crate::error::Error> {
+        for value in &self.values {
+            value.compile(compiler)?;
+        }
+        let count = u16::try_from(self.values.len()).map_err(|_| {
+            crate::error::Error::new(0, "".to_string(), "Too many array elements".to_string())
+        })?;
+        compiler.emit(OpCode::Array(count));
+        Ok(())
     }
 }
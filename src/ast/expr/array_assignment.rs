@@ -1,5 +1,7 @@
+use crate::ast::optimizer::{fold, fold_all, move_local};
 use crate::ast::{Expr, Resolver};
-use crate::Token;
+use crate::vm::{Compile, Compiler};
+use crate::{Environment, Error, Token, Value};
 use std::rc::Rc;
 
 #[derive(Debug)]
@@ -27,63 +29,70 @@ impl Resolver for ArrayAssignment {
         for index in self.indeces.clone() {
             index.clone().resolve(scopes)?;
         }
+        scopes.check_assignable(&self.name)?;
         scopes.resolve_local(Rc::as_ptr(&self) as *const dyn Expr, &self.name);
         Ok(())
     }
 }
 
 impl Expr for ArrayAssignment {
-    fn eval(&self) -> Result<Box<crate::ast::Value>, crate::error::Error> {
+    fn eval(&self) -> Result<crate::ast::Value, crate::error::Error> {
         let value = self.value.eval()?;
         let indeces = self
             .indeces
             .iter()
-            .map(|index| {
-                let i = index.eval()?;
-                if let crate::ast::Value::Number(i) = *i {
-                    if i.fract() == 0f64 && i >= 0f64 && i.is_finite() {
-                        Ok(i as usize)
-                    } else {
-                        Err(crate::error::Error::new(
-                            self.name.line,
-                            self.name.lexeme.clone(),
-                            "Index must be non-negative integer".to_string(),
-                        ))
-                    }
-                } else {
-                    Err(crate::error::Error::new(
+            .map(|index| index.eval())
+            .collect::<Result<Vec<_>, _>>()?;
+        // the array is taken out of the variable while it is updated, so the variable doesn't
+        // share it and it is changed in place
+        let local = unsafe { crate::LOCALS.get(&(self as *const dyn Expr)) }.copied();
+        let put = |array: Value| unsafe {
+            match local {
+                Some((distance, slot)) => crate::ENVIRONMENT
+                    .borrow_mut()
+                    .replace_at(distance, slot, &self.name, array),
+                None => Environment::replace_global(
+                    crate::ENVIRONMENT.clone(),
+                    self.name.symbol(),
+                    array,
+                )
+                .map_err(|message| {
+                    Error::new(
                         self.name.line,
                         self.name.lexeme.clone(),
-                        "Index must be a number".to_string(),
-                    ))
-                }
-            })
-            .collect::<Result<Vec<usize>, crate::error::Error>>();
-        let mut indeces_ = Vec::new();
-        for index in indeces? {
-            indeces_.push(index);
-        }
-        let distance = unsafe { crate::LOCALS.get(&(self as *const dyn Expr)) };
-        if let Some(distance) = distance {
-            unsafe {
-                crate::Environment::assign_array_at(
-                    crate::ENVIRONMENT.clone(),
-                    *distance,
-                    self.name.clone(),
-                    indeces_,
-                    value.clone(),
-                )?;
-            }
-        } else {
-            unsafe {
-                crate::Environment::assign_array(
-                    crate::Environment::get_global_mut(crate::ENVIRONMENT.clone()),
-                    self.name.clone(),
-                    indeces_,
-                    value.clone(),
-                )?;
+                        message.to_string(),
+                    )
+                }),
             }
+        };
+        let mut array = put(Value::Nil)?;
+        let result = array.set_element(&indeces, value.clone(), &self.name);
+        put(array)?;
+        result.map(|_| value)
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let value = fold(&self.value);
+        let indeces = fold_all(&self.indeces);
+        if Rc::ptr_eq(&value, &self.value) && indeces.is_none() {
+            return None;
+        }
+        let assignment: Rc<dyn Expr> = Rc::new(ArrayAssignment {
+            name: self.name.clone(),
+            indeces: indeces.unwrap_or_else(|| self.indeces.clone()),
+            value,
+        });
+        move_local(self, &assignment);
+        Some(assignment)
+    }
+}
+
+impl Compile for ArrayAssignment {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), crate::error::Error> {
+        self.value.compile(compiler)?;
+        for index in &self.indeces {
+            index.compile(compiler)?;
         }
-        Ok(value)
+        compiler.set_variable_index(&self.name, self.indeces.len() as u8)
     }
 }
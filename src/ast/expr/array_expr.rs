@@ -1,4 +1,6 @@
+use crate::ast::optimizer::fold;
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::Token;
 use std::rc::Rc;
 
@@ -23,11 +25,14 @@ impl Resolver for ArrayExpr {
 }
 
 impl Expr for ArrayExpr {
-    fn eval(&self) -> Result<Box<crate::ast::Value>, crate::error::Error> {
+    fn eval(&self) -> Result<crate::ast::Value, crate::error::Error> {
         let array = self.name.eval()?;
         let index = self.index.eval()?;
-        if let crate::ast::Value::Number(index) = *index {
-            if let crate::ast::Value::Array(array) = *array {
+        if let Some(value) = array.call_special(crate::Symbol::INDEX, vec![index.clone()]) {
+            return value;
+        }
+        if let crate::ast::Value::Number(index) = index {
+            if let crate::ast::Value::Array(array) = &array {
                 if index.is_nan() || index.is_infinite() || index < 0.0 {
                     return Err(crate::error::Error::new(
                         self.bracket.line,
@@ -35,19 +40,36 @@ impl Expr for ArrayExpr {
                         "Index must be a non-negative integer".to_string(),
                     ));
                 }
-                if index as usize >= array.len() {
-                    return Err(crate::error::Error::new(
+                match array.borrow().get(index as usize) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(crate::error::Error::new(
                         self.bracket.line,
                         self.bracket.lexeme.clone(),
                         "Index out of bounds".to_string(),
+                    )),
+                }
+            } else if let crate::ast::Value::String(s) = &array {
+                if index.is_nan() || index.is_infinite() || index < 0.0 {
+                    return Err(crate::error::Error::new(
+                        self.bracket.line,
+                        self.bracket.lexeme.clone(),
+                        "Index must be a non-negative integer".to_string(),
                     ));
                 }
-                Ok(array[index as usize].clone())
+                // strings are indexed by characters, not bytes
+                match s.chars().nth(index as usize) {
+                    Some(c) => Ok(crate::ast::Value::String(c.to_string().into())),
+                    None => Err(crate::error::Error::new(
+                        self.bracket.line,
+                        self.bracket.lexeme.clone(),
+                        "Index out of bounds".to_string(),
+                    )),
+                }
             } else {
                 Err(crate::error::Error::new(
                     self.bracket.line,
                     self.bracket.lexeme.clone(),
-                    "Can't index non-array value".to_string(),
+                    "Can't index non-array or non-string value".to_string(),
                 ))
             }
         } else {
@@ -58,4 +80,26 @@ impl Expr for ArrayExpr {
             ))
         }
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let (name, index) = (fold(&self.name), fold(&self.index));
+        if Rc::ptr_eq(&name, &self.name) && Rc::ptr_eq(&index, &self.index) {
+            return None;
+        }
+        Some(Rc::new(ArrayExpr {
+            name,
+            bracket: self.bracket.clone(),
+            index,
+        }))
+    }
+}
+
+impl Compile for ArrayExpr {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), crate::error::Error> {
+        self.name.compile(compiler)?;
+        self.index.compile(compiler)?;
+        compiler.set_line(self.bracket.line);
+        compiler.emit(OpCode::GetIndex);
+        Ok(())
+    }
 }
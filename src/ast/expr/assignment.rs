@@ -1,4 +1,6 @@
+use crate::ast::optimizer::{fold, move_local};
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler};
 use crate::{Environment, Error, Token, Value, ENVIRONMENT};
 use rlox_macro::Expr;
 use std::rc::Rc;
@@ -10,35 +12,50 @@ pub struct Assignment {
 }
 
 impl Expr for Assignment {
-    fn eval(&self) -> Result<Box<Value>, Error> {
+    fn eval(&self) -> Result<Value, Error> {
         let value = self.value.eval()?;
-        let distance = unsafe { crate::LOCALS.get(&(self as *const dyn Expr)) };
-        if let Some(distance) = distance {
+        crate::profiler::sample(self.name.line);
+        let local = unsafe { crate::LOCALS.get(&(self as *const dyn Expr)) };
+        if let Some((distance, slot)) = local {
             unsafe {
-                Environment::assign_at(
-                    crate::ENVIRONMENT.clone(),
-                    *distance,
-                    self.name.clone(),
-                    value.clone(),
-                )?;
+                ENVIRONMENT
+                    .borrow_mut()
+                    .assign_at(*distance, *slot, &self.name, value.clone())?;
             }
         } else {
             unsafe {
-                Environment::assign(
-                    Environment::get_global_mut(ENVIRONMENT.clone()),
-                    self.name.clone(),
-                    value.clone(),
-                )?;
+                Environment::assign(ENVIRONMENT.clone(), &self.name, value.clone())?;
             }
         }
         Ok(value)
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let value = fold(&self.value);
+        if Rc::ptr_eq(&value, &self.value) {
+            return None;
+        }
+        let assignment: Rc<dyn Expr> = Rc::new(Assignment {
+            name: self.name.clone(),
+            value,
+        });
+        move_local(self, &assignment);
+        Some(assignment)
+    }
 }
 
 impl Resolver for Assignment {
     fn resolve(self: Rc<Self>, scopes: &mut crate::Scopes) -> Result<(), Error> {
         self.value.clone().resolve(scopes)?;
+        scopes.check_assignable(&self.name)?;
         scopes.resolve_local(Rc::as_ptr(&self) as *const dyn Expr, &self.name);
         Ok(())
     }
 }
+
+impl Compile for Assignment {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        self.value.compile(compiler)?;
+        compiler.set_variable(&self.name)
+    }
+}
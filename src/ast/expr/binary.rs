@@ -1,5 +1,7 @@
+use crate::ast::optimizer::{evaluate, fold, is_literal};
 use crate::ast::{Expr, Resolver};
-use crate::{Error, Token, TokenType, Value};
+use crate::vm::{Compile, Compiler, OpCode};
+use crate::{Error, Symbol, Token, TokenType, Value};
 use rlox_macro::Expr;
 use std::rc::Rc;
 
@@ -12,12 +14,28 @@ pub struct Binary {
 }
 
 impl Expr for Binary {
-    fn eval(&self) -> Result<Box<Value>, Error> {
+    fn eval(&self) -> Result<Value, Error> {
         let left = self.left.eval()?;
         let right = self.right.eval()?;
+        crate::profiler::sample(self.operator.line);
+        // `a > b` is `b < a`, and `a >= b` is `!(a < b)`
+        let less = match self.operator.token_type {
+            TokenType::Less | TokenType::GreaterEqual => left.less_method(&right),
+            TokenType::Greater | TokenType::LessEqual => right.less_method(&left),
+            _ => None,
+        };
+        if let Some(less) = less {
+            let strict = matches!(
+                self.operator.token_type,
+                TokenType::Less | TokenType::Greater
+            );
+            return Ok(Value::Boolean(less? == strict));
+        }
         match self.operator.token_type {
             TokenType::Minus => {
-                if let Ok(v) = (*left) - (*right) {
+                if let Some(result) = left.operator_method(Symbol::SUB, &right) {
+                    result
+                } else if let Ok(v) = left - right {
                     Ok(v)
                 } else {
                     Err(Error::new(
@@ -28,7 +46,9 @@ impl Expr for Binary {
                 }
             }
             TokenType::Plus => {
-                if let Ok(v) = (*left) + (*right) {
+                if let Some(result) = left.operator_method(Symbol::ADD, &right) {
+                    result
+                } else if let Ok(v) = left + right {
                     Ok(v)
                 } else {
                     Err(Error::new(
@@ -39,7 +59,9 @@ impl Expr for Binary {
                 }
             }
             TokenType::Slash => {
-                if let Ok(v) = (*left) / (*right) {
+                if let Some(result) = left.operator_method(Symbol::DIV, &right) {
+                    result
+                } else if let Ok(v) = left / right {
                     Ok(v)
                 } else {
                     Err(Error::new(
@@ -50,7 +72,9 @@ impl Expr for Binary {
                 }
             }
             TokenType::Star => {
-                if let Ok(v) = (*left) * (*right) {
+                if let Some(result) = left.operator_method(Symbol::MUL, &right) {
+                    result
+                } else if let Ok(v) = left * right {
                     Ok(v)
                 } else {
                     Err(Error::new(
@@ -61,8 +85,8 @@ impl Expr for Binary {
                 }
             }
             TokenType::Greater => match left.cmp(&right) {
-                Ok(Some(std::cmp::Ordering::Greater)) => Ok(Box::new(Value::Boolean(true))),
-                Ok(_) => Ok(Box::new(Value::Boolean(false))),
+                Ok(Some(std::cmp::Ordering::Greater)) => Ok(Value::Boolean(true)),
+                Ok(_) => Ok(Value::Boolean(false)),
                 _ => Err(Error::new(
                     self.operator.line,
                     ">".to_string(),
@@ -71,9 +95,9 @@ impl Expr for Binary {
             },
             TokenType::GreaterEqual => match left.cmp(&right) {
                 Ok(Some(std::cmp::Ordering::Greater)) | Ok(Some(std::cmp::Ordering::Equal)) => {
-                    Ok(Box::new(Value::Boolean(true)))
+                    Ok(Value::Boolean(true))
                 }
-                Ok(_) => Ok(Box::new(Value::Boolean(false))),
+                Ok(_) => Ok(Value::Boolean(false)),
                 _ => Err(Error::new(
                     self.operator.line,
                     ">=".to_string(),
@@ -81,8 +105,8 @@ impl Expr for Binary {
                 )),
             },
             TokenType::Less => match left.cmp(&right) {
-                Ok(Some(std::cmp::Ordering::Less)) => Ok(Box::new(Value::Boolean(true))),
-                Ok(_) => Ok(Box::new(Value::Boolean(false))),
+                Ok(Some(std::cmp::Ordering::Less)) => Ok(Value::Boolean(true)),
+                Ok(_) => Ok(Value::Boolean(false)),
                 _ => Err(Error::new(
                     self.operator.line,
                     "<".to_string(),
@@ -91,17 +115,29 @@ impl Expr for Binary {
             },
             TokenType::LessEqual => match left.cmp(&right) {
                 Ok(Some(std::cmp::Ordering::Less)) | Ok(Some(std::cmp::Ordering::Equal)) => {
-                    Ok(Box::new(Value::Boolean(true)))
+                    Ok(Value::Boolean(true))
                 }
-                Ok(_) => Ok(Box::new(Value::Boolean(false))),
+                Ok(_) => Ok(Value::Boolean(false)),
                 _ => Err(Error::new(
                     self.operator.line,
                     "<=".to_string(),
                     "Binary operator <= only works with numbers".to_string(),
                 )),
             },
-            TokenType::EqualEqual => Ok(Box::new(Value::Boolean(left == right))),
-            TokenType::BangEqual => Ok(Box::new(Value::Boolean(left != right))),
+            TokenType::EqualEqual | TokenType::BangEqual => {
+                let equal = left.equals(&right)?;
+                Ok(Value::Boolean(
+                    equal == (self.operator.token_type == TokenType::EqualEqual),
+                ))
+            }
+            TokenType::InstanceOf => match right {
+                Value::Class(class) => Ok(Value::Boolean(left.instance_of(&class))),
+                _ => Err(Error::new(
+                    self.operator.line,
+                    "instanceof".to_string(),
+                    "Right operand of instanceof must be a class".to_string(),
+                )),
+            },
             _ => Err(Error::new(
                 self.operator.line,
                 self.operator.lexeme.clone(),
@@ -109,6 +145,25 @@ impl Expr for Binary {
             )),
         }
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let (left, right) = (fold(&self.left), fold(&self.right));
+        let binary = Binary {
+            operator: self.operator.clone(),
+            left,
+            right,
+        };
+        if is_literal(&binary.left) && is_literal(&binary.right) {
+            if let Some(literal) = evaluate(&binary, self.operator.line) {
+                return Some(literal);
+            }
+        }
+        if Rc::ptr_eq(&binary.left, &self.left) && Rc::ptr_eq(&binary.right, &self.right) {
+            None
+        } else {
+            Some(Rc::new(binary))
+        }
+    }
 }
 
 impl Resolver for Binary {
@@ -117,3 +172,33 @@ impl Resolver for Binary {
         self.right.clone().resolve(scopes)
     }
 }
+
+impl Compile for Binary {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        self.left.compile(compiler)?;
+        self.right.compile(compiler)?;
+        compiler.set_line(self.operator.line);
+        let op = match self.operator.token_type {
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Plus => OpCode::Add,
+            TokenType::Slash => OpCode::Divide,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Greater => OpCode::Greater,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            TokenType::Less => OpCode::Less,
+            TokenType::LessEqual => OpCode::LessEqual,
+            TokenType::EqualEqual => OpCode::Equal,
+            TokenType::BangEqual => OpCode::NotEqual,
+            TokenType::InstanceOf => OpCode::InstanceOf,
+            _ => {
+                return Err(Error::new(
+                    self.operator.line,
+                    self.operator.lexeme.clone(),
+                    "Unknown binary operator".to_string(),
+                ))
+            }
+        };
+        compiler.emit(op);
+        Ok(())
+    }
+}
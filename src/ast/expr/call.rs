@@ -1,5 +1,7 @@
+use crate::ast::optimizer::{fold, fold_all};
 use crate::ast::value::LoxCallable;
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::{Error, Token, Value};
 use std::rc::Rc;
 
@@ -20,8 +22,10 @@ impl std::fmt::Display for Call {
     }
 }
 
-impl Expr for Call {
-    fn eval(&self) -> Result<Box<Value>, Error> {
+impl Call {
+    /// Evaluate the callee and the arguments, and check they can be called together
+    pub fn prepare(&self) -> Result<(Value, Vec<Value>), Error> {
+        crate::profiler::sample(self.paren.line);
         let callee = self.callee.eval()?;
 
         let mut arguements = Vec::new();
@@ -35,7 +39,17 @@ impl Expr for Call {
                 self.paren.lexeme.clone(),
                 "Can only call functions and classes".to_string(),
             ))
-        } else if arguements.len() != callee.arity() {
+        } else if callee.is_variadic() && arguements.len() < callee.arity() {
+            Err(Error::new(
+                self.paren.line,
+                self.paren.lexeme.clone(),
+                format!(
+                    "Expected at least {} arguments but got {}.",
+                    callee.arity(),
+                    arguements.len()
+                ),
+            ))
+        } else if !callee.is_variadic() && arguements.len() != callee.arity() {
             Err(Error::new(
                 self.paren.line,
                 self.paren.lexeme.clone(),
@@ -46,11 +60,35 @@ impl Expr for Call {
                 ),
             ))
         } else {
-            callee.call(arguements)
+            Ok((callee, arguements))
         }
     }
 }
 
+impl Expr for Call {
+    fn eval(&self) -> Result<Value, Error> {
+        let (callee, arguments) = self.prepare()?;
+        callee.call(arguments)
+    }
+
+    fn as_call(&self) -> Option<&Call> {
+        Some(self)
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let callee = fold(&self.callee);
+        let arguments = fold_all(&self.arguments);
+        if Rc::ptr_eq(&callee, &self.callee) && arguments.is_none() {
+            return None;
+        }
+        Some(Rc::new(Call {
+            callee,
+            arguments: arguments.unwrap_or_else(|| self.arguments.clone()),
+            paren: self.paren.clone(),
+        }))
+    }
+}
+
 impl Resolver for Call {
     fn resolve(self: Rc<Self>, scopes: &mut crate::Scopes) -> Result<(), Error> {
         self.callee.clone().resolve(scopes)?;
@@ -60,3 +98,26 @@ impl Resolver for Call {
         Ok(())
     }
 }
+
+impl Call {
+    fn compile_call(&self, compiler: &mut Compiler, op: OpCode) -> Result<(), Error> {
+        self.callee.compile(compiler)?;
+        for arg in &self.arguments {
+            arg.compile(compiler)?;
+        }
+        compiler.set_line(self.paren.line);
+        compiler.emit(op);
+        Ok(())
+    }
+
+    /// Compile the call of a `return` in tail position, it reuses the frame of the caller
+    pub fn compile_tail(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        self.compile_call(compiler, OpCode::TailCall(self.arguments.len() as u8))
+    }
+}
+
+impl Compile for Call {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        self.compile_call(compiler, OpCode::Call(self.arguments.len() as u8))
+    }
+}
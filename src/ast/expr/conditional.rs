@@ -0,0 +1,74 @@
+use crate::ast::optimizer::{fold, is_literal};
+use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler, OpCode};
+use crate::{Error, Token, Value};
+use rlox_macro::Expr;
+use std::rc::Rc;
+
+/// `condition ? then_branch : else_branch`, only the branch chosen is evaluated
+#[derive(Expr, Debug)]
+pub struct Conditional {
+    pub condition: Rc<dyn Expr>,
+    pub question: Token,
+    pub then_branch: Rc<dyn Expr>,
+    pub else_branch: Rc<dyn Expr>,
+}
+
+impl Expr for Conditional {
+    fn eval(&self) -> Result<Value, Error> {
+        // like `and` and `or`, anything but `true` is false
+        if self.condition.eval()? == Value::Boolean(true) {
+            self.then_branch.eval()
+        } else {
+            self.else_branch.eval()
+        }
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let condition = fold(&self.condition);
+        let then_branch = fold(&self.then_branch);
+        let else_branch = fold(&self.else_branch);
+        if is_literal(&condition) {
+            // a literal condition picks the branch
+            return if condition.eval().is_ok_and(|c| c == Value::Boolean(true)) {
+                Some(then_branch)
+            } else {
+                Some(else_branch)
+            };
+        }
+        if Rc::ptr_eq(&condition, &self.condition)
+            && Rc::ptr_eq(&then_branch, &self.then_branch)
+            && Rc::ptr_eq(&else_branch, &self.else_branch)
+        {
+            None
+        } else {
+            Some(Rc::new(Conditional {
+                condition,
+                question: self.question.clone(),
+                then_branch,
+                else_branch,
+            }))
+        }
+    }
+}
+
+impl Resolver for Conditional {
+    fn resolve(self: Rc<Self>, scopes: &mut crate::Scopes) -> Result<(), Error> {
+        self.condition.clone().resolve(scopes)?;
+        self.then_branch.clone().resolve(scopes)?;
+        self.else_branch.clone().resolve(scopes)
+    }
+}
+
+impl Compile for Conditional {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        self.condition.compile(compiler)?;
+        compiler.set_line(self.question.line);
+        let else_jump = compiler.emit_jump(OpCode::JumpUnlessTrue);
+        self.then_branch.compile(compiler)?;
+        let end_jump = compiler.emit_jump(OpCode::Jump);
+        compiler.patch_jump(else_jump)?;
+        self.else_branch.compile(compiler)?;
+        compiler.patch_jump(end_jump)
+    }
+}
@@ -1,11 +1,17 @@
+use crate::ast::expr::optional_chain::short_circuit;
+use crate::ast::optimizer::fold;
+use crate::ast::value::LoxCallable;
 use crate::ast::{Expr, Resolver};
-use crate::Token;
+use crate::vm::{Compile, Compiler, OpCode};
+use crate::{Token, Value};
 use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Get {
     pub object: Rc<dyn Expr>,
     pub name: Token,
+    /// `object?.name`, the chain it is part of is nil when the object is nil
+    pub optional: bool,
 }
 
 impl Resolver for Get {
@@ -16,31 +22,64 @@ impl Resolver for Get {
 
 impl std::fmt::Display for Get {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<get>({} {})", self.object, self.name.lexeme)
+        let tag = if self.optional { "get?" } else { "get" };
+        write!(f, "<{tag}>({} {})", self.object, self.name.lexeme)
     }
 }
 
 impl Expr for Get {
-    fn eval(&self) -> Result<Box<crate::ast::value::Value>, crate::error::Error> {
+    fn eval(&self) -> Result<crate::ast::value::Value, crate::error::Error> {
         let obj = self.object.eval()?;
-        match obj.as_ref() {
-            crate::ast::value::Value::Instance(instance) => {
+        crate::profiler::sample(self.name.line);
+        let value = match &obj {
+            Value::Instance(instance) => {
                 let instance = instance.clone();
-                let value = instance.borrow().get(&self.name.lexeme, instance.clone());
-                match value {
-                    Some(value) => Ok(value),
-                    None => Err(crate::error::Error::new(
-                        self.name.line,
-                        format!("'{}'", self.name.lexeme),
-                        "Undefined property".to_string(),
-                    )),
-                }
+                let value = instance.borrow().get(self.name.symbol(), instance.clone());
+                value
             }
-            _ => Err(crate::error::Error::new(
+            Value::Class(class) => class.get_static(self.name.symbol()),
+            Value::Generator(_) => obj.builtin_method(self.name.symbol()),
+            Value::Namespace(namespace) => namespace.get(self.name.symbol()),
+            Value::Nil if self.optional => return Err(short_circuit()),
+            _ => {
+                return Err(crate::error::Error::new(
+                    self.name.line,
+                    self.name.lexeme.clone(),
+                    "Only instance have properties".to_string(),
+                ))
+            }
+        };
+        match value {
+            Some(value) if value.is_getter() => value.call(Vec::new()),
+            Some(value) => Ok(value),
+            None => Err(crate::error::Error::new(
                 self.name.line,
-                self.name.lexeme.clone(),
-                "Only instance have properties".to_string(),
+                format!("'{}'", self.name.lexeme),
+                "Undefined property".to_string(),
             )),
         }
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let object = fold(&self.object);
+        (!Rc::ptr_eq(&object, &self.object)).then(|| {
+            Rc::new(Get {
+                object,
+                name: self.name.clone(),
+                optional: self.optional,
+            }) as Rc<dyn Expr>
+        })
+    }
+}
+
+impl Compile for Get {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), crate::error::Error> {
+        self.object.compile(compiler)?;
+        compiler.set_line(self.name.line);
+        if self.optional {
+            compiler.emit_chain_jump();
+        }
+        compiler.emit(OpCode::GetProperty(self.name.symbol()));
+        Ok(())
+    }
 }
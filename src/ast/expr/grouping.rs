@@ -1,4 +1,6 @@
+use crate::ast::optimizer::{fold, is_literal};
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler};
 use crate::{Error, Value};
 use rlox_macro::Expr;
 use std::rc::Rc;
@@ -10,9 +12,20 @@ pub struct Grouping {
 }
 
 impl Expr for Grouping {
-    fn eval(&self) -> Result<Box<Value>, Error> {
+    fn eval(&self) -> Result<Value, Error> {
         self.expression.eval()
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let expression = fold(&self.expression);
+        if is_literal(&expression) {
+            Some(expression)
+        } else if Rc::ptr_eq(&expression, &self.expression) {
+            None
+        } else {
+            Some(Rc::new(Grouping { expression }))
+        }
+    }
 }
 
 impl Resolver for Grouping {
@@ -20,3 +33,9 @@ impl Resolver for Grouping {
         self.expression.clone().resolve(scopes)
     }
 }
+
+impl Compile for Grouping {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        self.expression.compile(compiler)
+    }
+}
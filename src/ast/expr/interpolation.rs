@@ -0,0 +1,61 @@
+use crate::ast::optimizer::fold_all;
+use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler, OpCode};
+use crate::{Error, Value};
+use std::rc::Rc;
+
+#[derive(Debug)]
+/// Interpolated string, the parts are concatenated by their `Display`
+pub struct Interpolation {
+    pub parts: Vec<Rc<dyn Expr>>,
+}
+
+impl std::fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = String::new();
+        for part in &self.parts {
+            parts.push_str(&format!("{} ", part));
+        }
+        write!(f, "<interp>({})", parts)
+    }
+}
+
+impl Expr for Interpolation {
+    fn eval(&self) -> Result<Value, Error> {
+        let mut result = String::new();
+        for part in &self.parts {
+            result.push_str(&part.eval()?.stringify()?);
+        }
+        Ok(Value::String(result.into()))
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        fold_all(&self.parts).map(|parts| Rc::new(Interpolation { parts }) as Rc<dyn Expr>)
+    }
+}
+
+impl Resolver for Interpolation {
+    fn resolve(self: Rc<Self>, scopes: &mut crate::Scopes) -> Result<(), Error> {
+        for part in self.parts.clone() {
+            part.resolve(scopes)?;
+        }
+        Ok(())
+    }
+}
+
+impl Compile for Interpolation {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        for part in &self.parts {
+            part.compile(compiler)?;
+        }
+        let count = u8::try_from(self.parts.len()).map_err(|_| {
+            Error::new(
+                0,
+                "".to_string(),
+                "Too many interpolation parts".to_string(),
+            )
+        })?;
+        compiler.emit(OpCode::Interpolate(count));
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use crate::ast::stmt::function::resolve_function;
+use crate::ast::stmt::Function;
+use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler};
+use crate::{Error, FunctionType, Scopes, Value};
+use std::rc::Rc;
+
+/// A function without a name, `fun (a) { ... }` or `(a) => ...`
+#[derive(Debug)]
+pub struct Lambda {
+    pub function: Rc<Function>,
+}
+
+impl std::fmt::Display for Lambda {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<l>{}", self.function)
+    }
+}
+
+impl Expr for Lambda {
+    fn eval(&self) -> Result<Value, Error> {
+        unsafe {
+            crate::Environment::capture(&crate::ENVIRONMENT);
+            Ok(Value::Fun(
+                self.function.clone(),
+                crate::ENVIRONMENT.clone(),
+            ))
+        }
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        Some(Rc::new(Lambda {
+            function: Rc::new(self.function.fold()?),
+        }))
+    }
+}
+
+impl Resolver for Lambda {
+    fn resolve(self: Rc<Self>, scopes: &mut Scopes) -> Result<(), Error> {
+        resolve_function(self.function.clone(), scopes, FunctionType::Function)
+    }
+}
+
+impl Compile for Lambda {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        compiler.function(&self.function, FunctionType::Function)
+    }
+}
@@ -1,5 +1,6 @@
 use crate::ast::{Expr, Resolver};
-use crate::{Error, Token, Value};
+use crate::vm::{Compile, Compiler, OpCode};
+use crate::{Error, Token, TokenType, Value};
 use rlox_macro::Expr;
 use std::rc::Rc;
 
@@ -9,7 +10,7 @@ pub struct Literal {
 }
 
 impl Expr for Literal {
-    fn eval(&self) -> Result<Box<Value>, Error> {
+    fn eval(&self) -> Result<Value, Error> {
         let value = self.value.token_type.value();
         match value {
             Some(v) => Ok(v),
@@ -27,3 +28,29 @@ impl Resolver for Literal {
         Ok(())
     }
 }
+
+impl Compile for Literal {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        compiler.set_line(self.value.line);
+        match self.value.token_type {
+            TokenType::True => {
+                compiler.emit(OpCode::True);
+            }
+            TokenType::False => {
+                compiler.emit(OpCode::False);
+            }
+            TokenType::Nil => {
+                compiler.emit(OpCode::Nil);
+            }
+            _ => {
+                let value = self.value.token_type.value().ok_or(Error::new(
+                    self.value.line,
+                    self.value.lexeme.clone(),
+                    "This litral can't be evaluate".to_string(),
+                ))?;
+                compiler.emit_constant(value)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
+use crate::ast::optimizer::{fold, is_literal};
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::{Error, Token, TokenType, Value};
 use rlox_macro::Expr;
 use std::rc::Rc;
 
+/// `and`, `or` and `??`, the right side is only evaluated when the left one doesn't decide
 #[derive(Expr, Debug)]
 pub struct Logic {
     pub operator: Token,
@@ -10,20 +13,46 @@ pub struct Logic {
     pub right: Rc<dyn Expr>,
 }
 
+impl Logic {
+    /// Whether the left value is the result, without evaluating the right side
+    fn short_circuits(&self, left: &Value) -> bool {
+        match self.operator.token_type {
+            TokenType::Or => *left == Value::Boolean(true),
+            TokenType::And => *left == Value::Boolean(false),
+            _ => *left != Value::Nil,
+        }
+    }
+}
+
 impl Expr for Logic {
-    fn eval(&self) -> Result<Box<Value>, Error> {
+    fn eval(&self) -> Result<Value, Error> {
         let left = self.left.eval()?;
-        if self.operator.token_type == TokenType::Or {
-            if *left == Value::Boolean(true) {
-                return Ok(left);
-            }
-        } else {
-            if *left == Value::Boolean(false) {
-                return Ok(left);
-            }
+        if self.short_circuits(&left) {
+            return Ok(left);
         }
         self.right.eval()
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let (left, right) = (fold(&self.left), fold(&self.right));
+        if is_literal(&left) {
+            // a literal left side decides whether the right one is evaluated
+            return if left.eval().is_ok_and(|left| self.short_circuits(&left)) {
+                Some(left)
+            } else {
+                Some(right)
+            };
+        }
+        if Rc::ptr_eq(&left, &self.left) && Rc::ptr_eq(&right, &self.right) {
+            None
+        } else {
+            Some(Rc::new(Logic {
+                operator: self.operator.clone(),
+                left,
+                right,
+            }))
+        }
+    }
 }
 
 impl Resolver for Logic {
@@ -32,3 +61,18 @@ impl Resolver for Logic {
         self.right.clone().resolve(scopes)
     }
 }
+
+impl Compile for Logic {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        self.left.compile(compiler)?;
+        compiler.set_line(self.operator.line);
+        // short circuit with the left value as the result
+        let jump = match self.operator.token_type {
+            TokenType::Or => compiler.emit_jump(OpCode::JumpIfTrueOrPop),
+            TokenType::And => compiler.emit_jump(OpCode::JumpIfFalseOrPop),
+            _ => compiler.emit_jump(OpCode::JumpIfNotNilOrPop),
+        };
+        self.right.compile(compiler)?;
+        compiler.patch_jump(jump)
+    }
+}
@@ -1,4 +1,5 @@
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler};
 use crate::{Error, Scopes, Token, Value};
 use rlox_macro::Expr;
 use std::rc::Rc;
@@ -9,7 +10,7 @@ pub struct Operator {
 }
 
 impl Expr for Operator {
-    fn eval(&self) -> Result<Box<Value>, Error> {
+    fn eval(&self) -> Result<Value, Error> {
         panic!("Evaluate an operator is not supported")
     }
 }
@@ -19,3 +20,12 @@ impl Resolver for Operator {
         Ok(())
     }
 }
+
+impl Compile for Operator {
+    fn compile(&self, _compiler: &mut Compiler) -> Result<(), Error> {
+        Err(Error::report(
+            self.operator.clone(),
+            "Compile an operator is not supported".to_string(),
+        ))
+    }
+}
@@ -0,0 +1,50 @@
+use crate::ast::optimizer::{fold, is_literal};
+use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler};
+use crate::{Error, Value};
+use rlox_macro::Expr;
+use std::rc::Rc;
+
+/// A chain of calls, indexes and property accesses with a `?.` link, like `a?.b.c()`. It is nil
+/// when the object of a `?.` is nil, without evaluating the rest of the chain.
+#[derive(Expr, Debug)]
+pub struct OptionalChain {
+    pub expression: Rc<dyn Expr>,
+}
+
+/// Unwinds from the `?.` link that found nil to the end of its chain
+pub fn short_circuit() -> Error {
+    Error::new(0, "".to_string(), "short circuit".to_string())
+}
+
+impl Expr for OptionalChain {
+    fn eval(&self) -> Result<Value, Error> {
+        match self.expression.eval() {
+            Err(e) if e.message == "short circuit" => Ok(Value::Nil),
+            result => result,
+        }
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let expression = fold(&self.expression);
+        if is_literal(&expression) {
+            Some(expression)
+        } else if Rc::ptr_eq(&expression, &self.expression) {
+            None
+        } else {
+            Some(Rc::new(OptionalChain { expression }))
+        }
+    }
+}
+
+impl Resolver for OptionalChain {
+    fn resolve(self: Rc<Self>, scopes: &mut crate::Scopes) -> Result<(), Error> {
+        self.expression.clone().resolve(scopes)
+    }
+}
+
+impl Compile for OptionalChain {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        compiler.optional_chain(&self.expression)
+    }
+}
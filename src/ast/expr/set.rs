@@ -1,4 +1,6 @@
+use crate::ast::optimizer::{fold, fold_all};
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::Token;
 use std::rc::Rc;
 
@@ -34,43 +36,40 @@ impl Resolver for Set {
 }
 
 impl Expr for Set {
-    fn eval(&self) -> Result<Box<crate::ast::value::Value>, crate::error::Error> {
+    fn eval(&self) -> Result<crate::ast::value::Value, crate::error::Error> {
         let obj = self.object.eval()?;
-        if let crate::ast::value::Value::Instance(instance) = *obj {
+        if let crate::ast::value::Value::Instance(_)
+        | crate::ast::value::Value::Class(_)
+        | crate::ast::value::Value::Namespace(_) = obj
+        {
             let value = self.value.eval()?;
+            crate::profiler::sample(self.name.line);
             if let Some(indeces) = &self.indeces {
                 let indeces = indeces
                     .into_iter()
                     .map(|index| index.eval())
                     .collect::<Result<Vec<_>, _>>()?;
-                let indeces = indeces
-                    .into_iter()
-                    .map(|index| {
-                        if let crate::ast::value::Value::Number(i) = *index {
-                            if i.fract() == 0f64 && i >= 0f64 && i.is_finite() {
-                                Ok(i as usize)
-                            } else {
-                                Err(crate::error::Error::new(
-                                    self.name.line,
-                                    self.name.lexeme.clone(),
-                                    "Index must be non-negative integer".to_string(),
-                                ))
-                            }
-                        } else {
-                            Err(crate::error::Error::new(
-                                self.name.line,
-                                self.name.lexeme.clone(),
-                                "Index must be a number".to_string(),
-                            ))
-                        }
-                    })
-                    .collect::<Result<Vec<usize>, crate::error::Error>>()?;
-                instance
-                    .borrow_mut()
-                    .set_array(&self.name, indeces, value.clone())?;
+                obj.set_field_element(&self.name, &indeces, value.clone())?;
                 Ok(value)
             } else {
-                instance.borrow_mut().set(&self.name.lexeme, value.clone());
+                match &obj {
+                    crate::ast::value::Value::Instance(instance) => {
+                        instance.borrow_mut().set(self.name.symbol(), value.clone())
+                    }
+                    crate::ast::value::Value::Class(class) => {
+                        class.set_static(self.name.symbol(), value.clone())
+                    }
+                    crate::ast::value::Value::Namespace(namespace) => namespace
+                        .set(self.name.symbol(), value.clone())
+                        .map_err(|message| {
+                            crate::error::Error::new(
+                                self.name.line,
+                                self.name.lexeme.clone(),
+                                message.to_string(),
+                            )
+                        })?,
+                    _ => unreachable!(),
+                }
                 Ok(value)
             }
         } else {
@@ -81,4 +80,44 @@ impl Expr for Set {
             ));
         }
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let (object, value) = (fold(&self.object), fold(&self.value));
+        let indeces = self.indeces.as_ref().map(|indeces| fold_all(indeces));
+        if Rc::ptr_eq(&object, &self.object)
+            && Rc::ptr_eq(&value, &self.value)
+            && indeces.as_ref().is_none_or(Option::is_none)
+        {
+            return None;
+        }
+        Some(Rc::new(Set {
+            object,
+            name: self.name.clone(),
+            value,
+            indeces: indeces
+                .zip(self.indeces.clone())
+                .map(|(folded, indeces)| folded.unwrap_or(indeces)),
+        }))
+    }
+}
+
+impl Compile for Set {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), crate::error::Error> {
+        self.object.compile(compiler)?;
+        self.value.compile(compiler)?;
+        if let Some(indeces) = &self.indeces {
+            for index in indeces {
+                index.compile(compiler)?;
+            }
+            compiler.set_line(self.name.line);
+            compiler.emit(OpCode::SetPropertyIndex(
+                self.name.symbol(),
+                indeces.len() as u8,
+            ));
+        } else {
+            compiler.set_line(self.name.line);
+            compiler.emit(OpCode::SetProperty(self.name.symbol()));
+        }
+        Ok(())
+    }
 }
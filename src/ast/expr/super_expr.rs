@@ -1,4 +1,6 @@
+use crate::ast::value::LoxCallable;
 use crate::ast::{Expr, Resolver, Value};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::{Token, TokenType};
 use std::rc::Rc;
 
@@ -23,6 +25,11 @@ impl Resolver for SuperExpr {
                 self.keyword.clone(),
                 "Can't use 'super' in a class with no superclass".to_string(),
             ));
+        } else if scopes.get_current_class() == Some(crate::ClassType::Static) {
+            return Err(crate::Error::report(
+                self.keyword.clone(),
+                "Can't use 'super' in a static member".to_string(),
+            ));
         }
         scopes.resolve_local(Rc::as_ptr(&self) as *const dyn Expr, &self.keyword);
         Ok(())
@@ -30,25 +37,33 @@ impl Resolver for SuperExpr {
 }
 
 impl Expr for SuperExpr {
-    fn eval(&self) -> Result<Box<crate::ast::Value>, crate::error::Error> {
+    fn eval(&self) -> Result<crate::ast::Value, crate::error::Error> {
         unsafe {
-            let distance = crate::LOCALS.get(&(self as *const dyn Expr)).unwrap();
-            let super_class = crate::ENVIRONMENT
-                .borrow()
-                .get_at(*distance, self.keyword.clone())?;
+            let (distance, slot) = crate::LOCALS.get(&(self as *const dyn Expr)).unwrap();
+            let super_class =
+                crate::ENVIRONMENT
+                    .borrow()
+                    .get_at(*distance, *slot, &self.keyword)?;
+            // `this` is the only name in the scope right inside `super`
             let this = crate::ENVIRONMENT.borrow().get_at(
                 distance - 1,
-                Token {
+                0,
+                &Token {
                     token_type: TokenType::This,
                     lexeme: "this".to_string(),
                     line: 0,
+                    column: 0,
                 },
             )?;
-            if let Value::Instance(this) = this.as_ref().clone() {
-                let method = super_class.get_method(&self.method.lexeme);
+            if let Value::Instance(this) = this {
+                let method = super_class.get_method(self.method.symbol());
                 if let Some(mut method) = method {
                     method.bind(this);
-                    Ok(method)
+                    if method.is_getter() {
+                        method.call(Vec::new())
+                    } else {
+                        Ok(method)
+                    }
                 } else {
                     Err(crate::Error::new(
                         self.method.line,
@@ -68,3 +83,17 @@ impl std::fmt::Display for SuperExpr {
         write!(f, "<super {}>", self.method.lexeme)
     }
 }
+
+impl Compile for SuperExpr {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), crate::error::Error> {
+        compiler.get_variable(&Token {
+            token_type: TokenType::This,
+            lexeme: "this".to_string(),
+            line: self.keyword.line,
+            column: 0,
+        })?;
+        compiler.get_variable(&self.keyword)?;
+        compiler.emit(OpCode::GetSuper(self.method.symbol()));
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler};
 use crate::{Error, Scopes, Token};
 use std::rc::Rc;
 
@@ -14,6 +15,12 @@ impl Resolver for This {
                 self.keyword.lexeme.clone(),
                 "Cannot use 'this' outside of a class".to_string(),
             ))
+        } else if scopes.get_current_class() == Some(crate::ClassType::Static) {
+            Err(Error::new(
+                self.keyword.line,
+                self.keyword.lexeme.clone(),
+                "Cannot use 'this' in a static member".to_string(),
+            ))
         } else {
             scopes.resolve_local(Rc::as_ptr(&self) as *const dyn Expr, &self.keyword);
             Ok(())
@@ -34,11 +41,17 @@ impl std::fmt::Debug for This {
 }
 
 impl Expr for This {
-    fn eval(&self) -> Result<Box<crate::ast::value::Value>, crate::error::Error> {
+    fn eval(&self) -> Result<crate::ast::value::Value, crate::error::Error> {
         unsafe {
             crate::ENVIRONMENT
                 .borrow()
-                .look_up_variable(self.keyword.clone(), self)
+                .look_up_variable(&self.keyword, self)
         }
     }
 }
+
+impl Compile for This {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        compiler.get_variable(&self.keyword)
+    }
+}
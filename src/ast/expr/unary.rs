@@ -1,4 +1,6 @@
+use crate::ast::optimizer::{evaluate, fold, is_literal};
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::{Error, Token, TokenType, Value};
 use rlox_macro::Expr;
 use std::rc::Rc;
@@ -11,11 +13,11 @@ pub struct Unary {
 }
 
 impl Expr for Unary {
-    fn eval(&self) -> Result<Box<Value>, Error> {
+    fn eval(&self) -> Result<Value, Error> {
         let right = self.right.eval()?;
         match self.operator.token_type {
             TokenType::Minus => {
-                if let Ok(v) = -(*right) {
+                if let Ok(v) = -right {
                     Ok(v)
                 } else {
                     Err(Error::new(
@@ -26,7 +28,7 @@ impl Expr for Unary {
                 }
             }
             TokenType::Bang => {
-                if let Ok(v) = !(*right) {
+                if let Ok(v) = !right {
                     Ok(v)
                 } else {
                     Err(Error::new(
@@ -43,6 +45,19 @@ impl Expr for Unary {
             )),
         }
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Expr>> {
+        let unary = Unary {
+            operator: self.operator.clone(),
+            right: fold(&self.right),
+        };
+        if is_literal(&unary.right) {
+            if let Some(literal) = evaluate(&unary, self.operator.line) {
+                return Some(literal);
+            }
+        }
+        (!Rc::ptr_eq(&unary.right, &self.right)).then(|| Rc::new(unary) as Rc<dyn Expr>)
+    }
 }
 
 impl Resolver for Unary {
@@ -50,3 +65,22 @@ impl Resolver for Unary {
         self.right.clone().resolve(scopes)
     }
 }
+
+impl Compile for Unary {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        self.right.compile(compiler)?;
+        compiler.set_line(self.operator.line);
+        match self.operator.token_type {
+            TokenType::Minus => compiler.emit(OpCode::Negate),
+            TokenType::Bang => compiler.emit(OpCode::Not),
+            _ => {
+                return Err(Error::new(
+                    self.operator.line,
+                    self.operator.lexeme.clone(),
+                    "Unknown unary operator".to_string(),
+                ))
+            }
+        };
+        Ok(())
+    }
+}
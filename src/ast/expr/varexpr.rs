@@ -1,4 +1,5 @@
 use crate::ast::{Expr, Resolver};
+use crate::vm::{Compile, Compiler};
 use crate::{Error, Token, Value};
 use std::rc::Rc;
 
@@ -15,18 +16,26 @@ impl std::fmt::Display for VarExpr {
 }
 
 impl Expr for VarExpr {
-    fn eval(&self) -> Result<Box<Value>, Error> {
+    fn eval(&self) -> Result<Value, Error> {
+        crate::profiler::sample(self.name.line);
         unsafe {
             crate::ENVIRONMENT
                 .borrow()
-                .look_up_variable(self.name.clone(), self)
+                .look_up_variable(&self.name, self)
         }
     }
 }
 
 impl Resolver for VarExpr {
     fn resolve(self: Rc<Self>, scopes: &mut crate::Scopes) -> Result<(), Error> {
-        if !scopes.is_empty() && scopes.peek().unwrap().get(&self.name.lexeme) == Some(&false) {
+        if !scopes.is_empty()
+            && scopes
+                .peek()
+                .unwrap()
+                .get(&self.name.lexeme)
+                .map(|(defined, _, _)| *defined)
+                == Some(false)
+        {
             Err(Error::new(
                 self.name.line,
                 self.name.lexeme.clone(),
@@ -38,3 +47,9 @@ impl Resolver for VarExpr {
         }
     }
 }
+
+impl Compile for VarExpr {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        compiler.get_variable(&self.name)
+    }
+}
@@ -0,0 +1,107 @@
+//! Constant folding and dead code elimination, run between resolution and execution
+//!
+//! A node returns a replacement from `optimize` only when something changed, the nodes kept
+//! are the same `Rc` so the locals resolved by their address still apply.
+use crate::ast::expr::Literal;
+use crate::ast::stmt::Block;
+use crate::ast::{Expr, Stmt};
+use crate::{Token, TokenType, Value};
+use once_cell::sync::Lazy;
+use std::rc::Rc;
+
+pub fn optimize(statements: Vec<Rc<dyn Stmt>>) -> Vec<Rc<dyn Stmt>> {
+    fold_statements(&statements).unwrap_or(statements)
+}
+
+/// The folded expression, or the same one
+pub fn fold(expr: &Rc<dyn Expr>) -> Rc<dyn Expr> {
+    expr.optimize().unwrap_or_else(|| expr.clone())
+}
+
+pub fn fold_stmt(stmt: &Rc<dyn Stmt>) -> Rc<dyn Stmt> {
+    stmt.optimize().unwrap_or_else(|| stmt.clone())
+}
+
+/// Fold each expression, `None` if none changed
+pub fn fold_all(exprs: &[Rc<dyn Expr>]) -> Option<Vec<Rc<dyn Expr>>> {
+    let folded = exprs.iter().map(fold).collect::<Vec<_>>();
+    folded
+        .iter()
+        .zip(exprs)
+        .any(|(folded, expr)| !Rc::ptr_eq(folded, expr))
+        .then_some(folded)
+}
+
+/// Fold the statements and drop the ones left empty, `None` if none changed
+pub fn fold_statements(statements: &[Rc<dyn Stmt>]) -> Option<Vec<Rc<dyn Stmt>>> {
+    let mut changed = false;
+    let mut folded = Vec::new();
+    for statement in statements {
+        let stmt = fold_stmt(statement);
+        changed |= !Rc::ptr_eq(&stmt, statement);
+        // only statements that aren't declarations are pruned, dropping them moves no slot
+        if !is_pruned(&stmt) {
+            folded.push(stmt);
+        }
+    }
+    changed.then_some(folded)
+}
+
+pub fn is_literal(expr: &Rc<dyn Expr>) -> bool {
+    expr.type_name() == std::any::type_name::<Literal>()
+}
+
+/// Left in place of a pruned statement
+static mut PRUNED: Lazy<Rc<dyn Stmt>> = Lazy::new(|| {
+    Rc::new(Block {
+        statements: Vec::new(),
+    })
+});
+
+pub fn pruned() -> Rc<dyn Stmt> {
+    Rc::clone(unsafe { &*std::ptr::addr_of!(PRUNED) })
+}
+
+pub fn is_pruned(stmt: &Rc<dyn Stmt>) -> bool {
+    Rc::ptr_eq(stmt, unsafe { &*std::ptr::addr_of!(PRUNED) })
+}
+
+/// The value of a literal condition, if it is a boolean
+pub fn constant_condition(condition: &Rc<dyn Expr>) -> Option<bool> {
+    if !is_literal(condition) {
+        return None;
+    }
+    match condition.eval() {
+        Ok(Value::Boolean(b)) => Some(b),
+        _ => None,
+    }
+}
+
+/// Evaluate an expression of literals, `None` if it fails so the error happens at runtime
+pub fn evaluate(expr: &dyn Expr, line: usize) -> Option<Rc<dyn Expr>> {
+    let value = expr.eval().ok()?;
+    let token_type = match &value {
+        Value::Number(n) => TokenType::Number(*n),
+        Value::String(s) => TokenType::String(crate::Symbol::intern(s)),
+        Value::Boolean(true) => TokenType::True,
+        Value::Boolean(false) => TokenType::False,
+        Value::Nil => TokenType::Nil,
+        _ => return None,
+    };
+    Some(Rc::new(Literal {
+        value: Token {
+            token_type,
+            lexeme: value.to_string(),
+            line,
+            column: 0,
+        },
+    }))
+}
+
+/// Move the local resolved for a node to the node replacing it
+pub fn move_local(node: &(dyn Expr + 'static), replacement: &Rc<dyn Expr>) {
+    let locals = unsafe { &mut *std::ptr::addr_of_mut!(crate::LOCALS) };
+    if let Some(local) = locals.remove(&(node as *const dyn Expr)) {
+        locals.insert(Rc::as_ptr(replacement), local);
+    }
+}
@@ -1,10 +1,14 @@
 //! statement in AST
 use crate::ast::Resolver;
 use crate::error::Error;
+use crate::generator::Resume;
+use crate::vm::Compile;
+use std::rc::Rc;
 
 pub mod class;
 pub use class::Class;
 pub use class::Instance;
+pub use class::LoxClass;
 pub mod expression;
 pub use expression::Expression;
 pub mod print;
@@ -17,13 +21,31 @@ pub mod if_expr;
 pub use if_expr::IfExpr;
 pub mod while_expr;
 pub use while_expr::WhileExpr;
+pub mod for_in;
+pub use for_in::ForIn;
 pub mod function;
 pub use function::Function;
 pub mod return_expr;
 pub use return_expr::ReturnExpr;
+pub mod yield_expr;
+pub use yield_expr::YieldExpr;
+pub mod import;
+pub use import::Import;
 
-pub trait Stmt: std::fmt::Display + std::fmt::Debug + Resolver {
+pub trait Stmt: std::fmt::Display + std::fmt::Debug + Resolver + Compile {
     fn interpret(&self) -> Result<(), Error>;
+    /// The statement with its constant parts folded and dead branches pruned, `None` to keep
+    /// this one
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        None
+    }
+    /// Run the statement in the body of a generator. A `yield` suspends it with
+    /// `Err("yield")`, and each statement it unwinds through pushes to `resume` where to
+    /// continue. Run again with those entries, a statement continues from its own instead of
+    /// starting over.
+    fn generate(&self, _resume: &mut Vec<Resume>) -> Result<(), Error> {
+        self.interpret()
+    }
     fn type_name(&self) -> String {
         std::any::type_name::<Self>().to_string()
     }
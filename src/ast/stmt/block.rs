@@ -1,6 +1,8 @@
+use crate::ast::optimizer::fold_statements;
 use crate::ast::{Resolver, Stmt};
+use crate::generator::{is_yield, Resume};
+use crate::vm::{Compile, Compiler};
 use crate::{Environment, Error, Scopes};
-use once_cell::sync::Lazy;
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -22,19 +24,20 @@ impl std::fmt::Display for Block {
 
 impl Stmt for Block {
     fn interpret(&self) -> Result<(), Error> {
-        unsafe {
-            let previous = Rc::new(RefCell::new(crate::ENVIRONMENT.borrow().clone()));
-
-            crate::ENVIRONMENT = Lazy::new(|| Rc::new(RefCell::new(Environment::new(None))));
-            crate::ENVIRONMENT
-                .borrow_mut()
-                .set_enclosing(Some(previous.clone()));
-            for statement in &self.statements {
-                statement.interpret()?;
-            }
-            crate::ENVIRONMENT.borrow_mut().from(previous);
-            Ok(())
-        }
+        let environment = unsafe { Environment::new(Some(crate::ENVIRONMENT.clone())) };
+        self.execute_in(Rc::new(RefCell::new(environment)))
+    }
+
+    fn generate(&self, resume: &mut Vec<Resume>) -> Result<(), Error> {
+        self.generate_in(resume, || unsafe {
+            Rc::new(RefCell::new(Environment::new(Some(
+                crate::ENVIRONMENT.clone(),
+            ))))
+        })
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        self.fold().map(|block| Rc::new(block) as Rc<dyn Stmt>)
     }
 }
 
@@ -50,10 +53,65 @@ impl Resolver for Block {
 }
 
 impl Block {
-    pub fn excute(&self) -> Result<(), Error> {
+    /// The block with its statements folded, `None` if none changed
+    pub fn fold(&self) -> Option<Block> {
+        let statements = fold_statements(&self.statements)?;
+        Some(Block { statements })
+    }
+
+    /// Execute the statements in the environment, and restore the current one after
+    pub fn execute_in(&self, environment: Rc<RefCell<Environment>>) -> Result<(), Error> {
+        unsafe {
+            let previous = std::mem::replace(&mut *crate::ENVIRONMENT, environment);
+            let result = self
+                .statements
+                .iter()
+                .try_for_each(|statement| statement.interpret());
+            *crate::ENVIRONMENT = previous;
+            result
+        }
+    }
+
+    /// `execute_in` for a generator, in the environment and from the statement the block was
+    /// suspended at when resuming
+    pub fn generate_in(
+        &self,
+        resume: &mut Vec<Resume>,
+        environment: impl FnOnce() -> Rc<RefCell<Environment>>,
+    ) -> Result<(), Error> {
+        let (start, environment) = match resume.pop() {
+            Some(Resume::Block { index, environment }) => (index, environment),
+            None => (0, environment()),
+            Some(_) => unreachable!(),
+        };
+        unsafe {
+            let previous = std::mem::replace(&mut *crate::ENVIRONMENT, environment.clone());
+            let mut result = Ok(());
+            for (index, statement) in self.statements.iter().enumerate().skip(start) {
+                result = statement.generate(resume);
+                if is_yield(&result) {
+                    resume.push(Resume::Block {
+                        index,
+                        environment: environment.clone(),
+                    });
+                }
+                if result.is_err() {
+                    break;
+                }
+            }
+            *crate::ENVIRONMENT = previous;
+            result
+        }
+    }
+}
+
+impl Compile for Block {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        compiler.begin_scope();
         for statement in &self.statements {
-            statement.interpret()?;
+            statement.compile(compiler)?;
         }
+        compiler.end_scope();
         Ok(())
     }
 }
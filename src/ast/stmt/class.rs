@@ -1,7 +1,11 @@
 use crate::ast::expr::VarExpr;
+use crate::ast::optimizer::fold_all;
 use crate::ast::stmt::Function;
 use crate::ast::{Expr, Resolver, Stmt};
-use crate::{Error, FunctionType, Scopes, Token, Value};
+use crate::gc::{trace_value, Trace};
+use crate::interner::Symbol;
+use crate::vm::{Compile, Compiler, OpCode};
+use crate::{Environment, Error, FunctionType, Scopes, Token, Value};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -10,6 +14,10 @@ use std::rc::Rc;
 pub struct Class {
     pub name: Token,
     pub methods: Vec<Rc<Function>>,
+    /// Methods declared with `class`, called on the class itself
+    pub static_methods: Vec<Rc<Function>>,
+    /// `class name = value;`, evaluated once the class is declared
+    pub static_fields: Vec<(Token, Rc<dyn Expr>)>,
     pub super_class: Option<Rc<VarExpr>>,
 }
 
@@ -39,19 +47,31 @@ impl Resolver for Class {
                 ));
             }
             super_class.resolve(scopes)?;
+        }
+        // the static members are outside the scopes of `this` and `super`
+        scopes.set_current_class(Some(crate::ClassType::Static));
+        for (_, value) in &self.static_fields {
+            value.clone().resolve(scopes)?;
+        }
+        for method in &self.static_methods {
+            crate::ast::stmt::function::resolve_function(
+                method.clone(),
+                scopes,
+                FunctionType::Method,
+            )?;
+        }
+        if self.super_class.is_some() {
             scopes.set_current_class(Some(crate::ClassType::SubClass));
+        } else {
+            scopes.set_current_class(Some(crate::ClassType::Class));
         }
         if self.super_class.is_some() {
             scopes.begin_scope();
-            if let Some(scope) = scopes.peek_mut() {
-                scope.insert("super".to_string(), true);
-            }
+            scopes.define_name("super".to_string());
         }
 
         scopes.begin_scope();
-        if let Some(scope) = scopes.peek_mut() {
-            scope.insert("this".to_string(), true);
-        }
+        scopes.define_name("this".to_string());
         for method in &self.methods {
             if method.name.lexeme == "init" {
                 crate::ast::stmt::function::resolve_function(
@@ -79,13 +99,11 @@ impl Resolver for Class {
 impl Stmt for Class {
     fn interpret(&self) -> Result<(), crate::error::Error> {
         unsafe {
-            crate::ENVIRONMENT
-                .borrow_mut()
-                .define(self.name.lexeme.clone(), Box::new(Value::Nil));
             let mut super_class = None;
             if let Some(super_cls) = self.super_class.clone() {
-                super_class = Some(super_cls.eval()?);
-                if !super_class.as_ref().unwrap().is_class() {
+                if let Value::Class(class) = super_cls.eval()? {
+                    super_class = Some(class);
+                } else {
                     return Err(crate::Error::new(
                         self.name.line,
                         self.name.lexeme.clone(),
@@ -93,102 +111,267 @@ impl Stmt for Class {
                     ));
                 }
             }
+            let mut statics = HashMap::new();
+            Environment::capture(&crate::ENVIRONMENT);
+            for method in &self.static_methods {
+                statics.insert(
+                    method.name.symbol(),
+                    Value::Fun(method.clone(), crate::ENVIRONMENT.clone()),
+                );
+            }
+            // the methods close over an environment holding `super`
+            let mut closure = crate::ENVIRONMENT.clone();
+            if let Some(super_class) = &super_class {
+                let mut environment = Environment::new(Some(closure));
+                environment.define(Symbol::SUPER, Value::Class(super_class.clone()));
+                closure = Rc::new(RefCell::new(environment));
+            }
+            Environment::capture(&closure);
             let mut methods = HashMap::new();
             for method in self.methods.clone() {
                 if method.name.lexeme == "init" {
                     (*(Rc::as_ptr(&method) as *mut Function)).is_initializer = true;
                 }
                 methods.insert(
-                    method.name.lexeme.clone(),
-                    Box::new(Value::Fun(method.clone(), None, super_class.clone())),
+                    method.name.symbol(),
+                    Value::Fun(method.clone(), closure.clone()),
                 );
             }
-            let class = Box::new(Value::Class {
-                class: self.name.lexeme.clone(),
+            let class = crate::gc::track(Rc::new(LoxClass {
+                name: self.name.lexeme.clone(),
                 methods,
+                statics: RefCell::new(statics),
                 super_class,
-            });
-            crate::Environment::assign(crate::ENVIRONMENT.clone(), self.name.clone(), class)?;
+            }));
+            crate::ENVIRONMENT
+                .borrow_mut()
+                .define(self.name.symbol(), Value::Class(class.clone()));
+            // the static fields can refer to the class
+            for (name, value) in &self.static_fields {
+                class.set_static(name.symbol(), value.eval()?);
+            }
         }
         Ok(())
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        let methods = fold_methods(&self.methods);
+        let static_methods = fold_methods(&self.static_methods);
+        let values = self
+            .static_fields
+            .iter()
+            .map(|(_, value)| value.clone())
+            .collect::<Vec<_>>();
+        let values = fold_all(&values);
+        if methods.is_none() && static_methods.is_none() && values.is_none() {
+            return None;
+        }
+        Some(Rc::new(Class {
+            methods: methods.unwrap_or_else(|| self.methods.clone()),
+            static_methods: static_methods.unwrap_or_else(|| self.static_methods.clone()),
+            static_fields: match values {
+                Some(values) => self
+                    .static_fields
+                    .iter()
+                    .zip(values)
+                    .map(|((name, _), value)| (name.clone(), value))
+                    .collect(),
+                None => self.static_fields.clone(),
+            },
+            ..self.clone()
+        }))
+    }
+}
+
+/// Fold the body of each method, `None` if none changed
+fn fold_methods(methods: &[Rc<Function>]) -> Option<Vec<Rc<Function>>> {
+    let folded = methods
+        .iter()
+        .map(|method| method.fold().map(Rc::new))
+        .collect::<Vec<_>>();
+    if folded.iter().all(Option::is_none) {
+        return None;
+    }
+    Some(
+        folded
+            .into_iter()
+            .zip(methods)
+            .map(|(folded, method)| folded.unwrap_or_else(|| method.clone()))
+            .collect(),
+    )
+}
+
+/// A class at runtime, shared by the class value and its instances
+#[derive(Debug)]
+pub struct LoxClass {
+    pub name: String,
+    pub methods: HashMap<Symbol, Value>,
+    /// The static methods and fields
+    pub statics: RefCell<HashMap<Symbol, Value>>,
+    pub super_class: Option<Rc<LoxClass>>,
+}
+
+impl LoxClass {
+    /// Look the method up in the class, then in its superclasses
+    pub fn find_method(&self, name: Symbol) -> Option<Value> {
+        if let Some(method) = self.methods.get(&name) {
+            Some(method.clone())
+        } else if let Some(super_class) = &self.super_class {
+            super_class.find_method(name)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the class is `other` or one of its subclasses
+    pub fn inherits(&self, other: &Rc<LoxClass>) -> bool {
+        std::ptr::eq(self, Rc::as_ptr(other))
+            || self
+                .super_class
+                .as_ref()
+                .is_some_and(|super_class| super_class.inherits(other))
+    }
+
+    /// Look the static member up in the class, then in its superclasses
+    pub fn get_static(&self, name: Symbol) -> Option<Value> {
+        if let Some(value) = self.statics.borrow().get(&name) {
+            Some(value.clone())
+        } else if let Some(super_class) = &self.super_class {
+            super_class.get_static(name)
+        } else {
+            None
+        }
+    }
+
+    /// Take a static field out of the class to update it, one of a super class is copied
+    pub fn take_static(&self, name: Symbol) -> Option<Value> {
+        let own = self.statics.borrow_mut().remove(&name);
+        own.or_else(|| self.super_class.as_ref()?.get_static(name))
+    }
+
+    pub fn set_static(&self, name: Symbol, value: Value) {
+        self.statics.borrow_mut().insert(name, value);
+    }
+}
+
+impl Trace for LoxClass {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        let Ok(statics) = self.statics.try_borrow() else {
+            return false;
+        };
+        for value in self.methods.values().chain(statics.values()) {
+            trace_value(value, visit);
+        }
+        if let Some(super_class) = &self.super_class {
+            visit(Rc::as_ptr(super_class) as *const ());
+        }
+        true
+    }
+
+    fn clear(&self) {
+        if let Ok(mut statics) = self.statics.try_borrow_mut() {
+            statics.clear();
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Instance {
-    pub class: String,
-    pub fields: HashMap<String, Box<Value>>,
-    pub methods: HashMap<String, Box<Value>>,
-    pub super_class: Option<Box<Value>>,
+    pub class: Rc<LoxClass>,
+    pub fields: HashMap<Symbol, Value>,
 }
 
 impl std::fmt::Display for Instance {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<instance of {}>", self.class)
+        write!(f, "<instance of {}>", self.class.name)
     }
 }
 
 impl Instance {
-    pub fn get(&self, name: &str, this: Rc<RefCell<Self>>) -> Option<Box<Value>> {
-        if self.fields.contains_key(name) {
-            self.fields.get(name).cloned()
-        } else if self.methods.contains_key(name) {
-            let mut method = self.methods.get(name).cloned().unwrap();
-            method.bind(this);
-            Some(method)
-        } else if let Some(super_class) = self.super_class.clone() {
-            let mut method = super_class.get_method(name);
+    pub fn get(&self, name: Symbol, this: Rc<RefCell<Self>>) -> Option<Value> {
+        if let Some(field) = self.fields.get(&name) {
+            Some(field.clone())
+        } else {
+            let mut method = self.class.find_method(name);
             method.as_mut().map(|x| x.bind(this));
             method
-        } else {
-            None
         }
     }
 
-    pub fn set(&mut self, name: &str, value: Box<Value>) {
-        self.fields.insert(name.to_string(), value);
-    }
-
-    pub fn set_array(
-        &mut self,
-        name: &Token,
-        indeces: Vec<usize>,
-        value: Box<Value>,
-    ) -> Result<(), Error> {
-        let array = self.fields.get_mut(&name.lexeme).ok_or(Error::new(
-            name.line,
-            name.lexeme.to_string(),
-            "Undefined property".to_string(),
-        ))?;
-        if let Value::Array(array) = array.as_mut() {
-            let mut array = array;
-            for index in indeces.iter().take(indeces.len() - 1) {
-                if let Value::Array(a) = array
-                    .get_mut(*index)
-                    .ok_or(Error::new(
-                        name.line,
-                        name.lexeme.to_string(),
-                        "Index out of bounds".to_string(),
-                    ))?
-                    .as_mut()
-                {
-                    array = a;
-                } else {
-                    return Err(Error::new(
-                        name.line,
-                        name.lexeme.to_string(),
-                        "Index must be an array".to_string(),
-                    ));
-                }
+    pub fn set(&mut self, name: Symbol, value: Value) {
+        self.fields.insert(name, value);
+    }
+}
+
+impl Trace for RefCell<Instance> {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        let Ok(instance) = self.try_borrow() else {
+            return false;
+        };
+        visit(Rc::as_ptr(&instance.class) as *const ());
+        for value in instance.fields.values() {
+            trace_value(value, visit);
+        }
+        true
+    }
+
+    fn clear(&self) {
+        if let Ok(mut instance) = self.try_borrow_mut() {
+            instance.fields.clear();
+        }
+    }
+}
+
+impl Compile for Class {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        // define the name first so the methods can refer to the class
+        compiler.declare_variable(&self.name)?;
+        compiler.emit(OpCode::Nil);
+        compiler.define_variable(&self.name)?;
+
+        if let Some(super_class) = &self.super_class {
+            compiler.begin_scope();
+            super_class.compile(compiler)?;
+            compiler.add_local("super")?;
+            compiler.mark_initialized();
+        }
+        for method in &self.methods {
+            compiler.emit_constant(Value::String(method.name.lexeme.as_str().into()))?;
+            if method.name.lexeme == "init" {
+                compiler.function(method, FunctionType::Initializer)?;
+            } else {
+                compiler.function(method, FunctionType::Method)?;
             }
-            array[*indeces.last().unwrap()] = value;
-            Ok(())
-        } else {
-            Err(Error::new(
-                name.line,
-                name.lexeme.to_string(),
-                "Not an array".to_string(),
-            ))
         }
+        for method in &self.static_methods {
+            compiler.emit_constant(Value::String(method.name.lexeme.as_str().into()))?;
+            compiler.function(method, FunctionType::Function)?;
+        }
+        compiler.set_line(self.name.line);
+        let name = self.name.symbol();
+        let too_many =
+            |_| Error::report(self.name.clone(), "Too many methods in class".to_string());
+        let methods = u8::try_from(self.methods.len()).map_err(too_many)?;
+        let statics = u8::try_from(self.static_methods.len()).map_err(too_many)?;
+        compiler.emit(OpCode::Class {
+            name,
+            methods,
+            statics,
+            has_super: self.super_class.is_some(),
+        });
+        compiler.set_variable(&self.name)?;
+        compiler.emit(OpCode::Pop);
+        // the static fields can refer to the class
+        for (name, value) in &self.static_fields {
+            compiler.get_variable(&self.name)?;
+            value.compile(compiler)?;
+            compiler.set_line(name.line);
+            compiler.emit(OpCode::SetProperty(name.symbol()));
+            compiler.emit(OpCode::Pop);
+        }
+        if self.super_class.is_some() {
+            compiler.end_scope();
+        }
+        Ok(())
     }
 }
@@ -1,5 +1,6 @@
 use crate::ast::expr::Expr;
 use crate::ast::{Resolver, Stmt};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::{Error, Scopes};
 use rlox_macro::Expr;
 use std::rc::Rc;
@@ -14,6 +15,11 @@ impl Stmt for Expression {
         self.expression.eval()?;
         Ok(())
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        let expression = self.expression.optimize()?;
+        Some(Rc::new(Expression { expression }))
+    }
 }
 
 impl Resolver for Expression {
@@ -21,3 +27,11 @@ impl Resolver for Expression {
         self.expression.clone().resolve(scopes)
     }
 }
+
+impl Compile for Expression {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        self.expression.compile(compiler)?;
+        compiler.emit(OpCode::Pop);
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use crate::ast::optimizer::fold;
+use crate::ast::stmt::Block;
+use crate::ast::value::LoxCallable;
+use crate::ast::{Expr, Resolver, Stmt, Value};
+use crate::generator::{is_yield, Resume};
+use crate::vm::{Compile, Compiler, OpCode};
+use crate::{Environment, Error, Scopes, Token};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// `for (var name in iterable) body`, the body runs in a new scope holding `name` each time
+#[derive(Debug)]
+pub struct ForIn {
+    pub name: Token,
+    /// The `in` keyword, where errors of the iteration are reported
+    pub keyword: Token,
+    pub iterable: Rc<dyn Expr>,
+    pub body: Rc<Block>,
+}
+
+impl std::fmt::Display for ForIn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<for>({} in {} {})", self.name, self.iterable, self.body)
+    }
+}
+
+impl Stmt for ForIn {
+    fn interpret(&self) -> Result<(), Error> {
+        let call = &mut |method: Value| method.call(Vec::new());
+        let iterable = self.iterable.eval()?.iterable(&self.keyword, call)?;
+        let mut position = 0;
+        while let Some(element) = iterable.next_element(&mut position, &self.keyword, call)? {
+            crate::interrupt::check()?;
+            crate::limits::step()?;
+            let mut environment = unsafe { Environment::new(Some(crate::ENVIRONMENT.clone())) };
+            environment.define(self.name.symbol(), element);
+            self.body.execute_in(Rc::new(RefCell::new(environment)))?;
+        }
+        Ok(())
+    }
+
+    fn generate(&self, resume: &mut Vec<Resume>) -> Result<(), Error> {
+        let call = &mut |method: Value| method.call(Vec::new());
+        let (iterable, mut position, mut resuming) = match resume.pop() {
+            Some(Resume::ForIn { iterable, position }) => (iterable, position, true),
+            None => (
+                self.iterable.eval()?.iterable(&self.keyword, call)?,
+                0,
+                false,
+            ),
+            Some(_) => unreachable!(),
+        };
+        loop {
+            let result = if resuming {
+                resuming = false;
+                // the block continues in the environment it was suspended in
+                self.body.generate_in(resume, || unreachable!())
+            } else {
+                let Some(element) = iterable.next_element(&mut position, &self.keyword, call)?
+                else {
+                    return Ok(());
+                };
+                crate::interrupt::check()?;
+                crate::limits::step()?;
+                let mut environment = unsafe { Environment::new(Some(crate::ENVIRONMENT.clone())) };
+                environment.define(self.name.symbol(), element);
+                self.body
+                    .generate_in(resume, || Rc::new(RefCell::new(environment)))
+            };
+            if is_yield(&result) {
+                resume.push(Resume::ForIn {
+                    iterable: iterable.clone(),
+                    position,
+                });
+            }
+            result?;
+        }
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        let iterable = fold(&self.iterable);
+        let body = self.body.fold();
+        if Rc::ptr_eq(&iterable, &self.iterable) && body.is_none() {
+            return None;
+        }
+        Some(Rc::new(ForIn {
+            name: self.name.clone(),
+            keyword: self.keyword.clone(),
+            iterable,
+            body: body.map_or_else(|| self.body.clone(), Rc::new),
+        }))
+    }
+}
+
+impl Resolver for ForIn {
+    fn resolve(self: Rc<Self>, scopes: &mut Scopes) -> Result<(), Error> {
+        self.iterable.clone().resolve(scopes)?;
+        // the body shares the scope of the loop variable, as it runs in its environment
+        scopes.begin_scope();
+        scopes.declare(self.name.clone())?;
+        scopes.define(self.name.clone());
+        for statement in self.body.statements.clone() {
+            statement.resolve(scopes)?;
+        }
+        scopes.end_scope();
+        Ok(())
+    }
+}
+
+impl Compile for ForIn {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        // the iterable and the position in it live in slots the user can't name
+        compiler.begin_scope();
+        self.iterable.compile(compiler)?;
+        compiler.set_line(self.keyword.line);
+        compiler.emit(OpCode::Iterable);
+        let slot = compiler.add_hidden_local("(iterable)")?;
+        compiler.emit_constant(Value::Number(0.0))?;
+        compiler.add_hidden_local("(position)")?;
+
+        let loop_start = compiler.loop_start();
+        let exit_jump = compiler.emit(OpCode::ForNext(slot, 0));
+        compiler.begin_scope();
+        compiler.add_local(&self.name.lexeme)?;
+        compiler.mark_initialized();
+        for statement in &self.body.statements {
+            statement.compile(compiler)?;
+        }
+        compiler.end_scope();
+        compiler.emit_loop(loop_start)?;
+        compiler.patch_jump(exit_jump)?;
+        compiler.end_scope();
+        Ok(())
+    }
+}
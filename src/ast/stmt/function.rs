@@ -1,5 +1,8 @@
 use crate::ast::stmt::Block;
 use crate::ast::{Resolver, Stmt};
+use crate::interner::Symbol;
+use crate::profiler::FunctionId;
+use crate::vm::{Compile, Compiler};
 use crate::{Error, FunctionType, Scopes, Token, Value};
 use std::rc::Rc;
 
@@ -9,6 +12,12 @@ pub struct Function {
     pub params: Vec<Token>,
     pub body: Rc<Block>,
     pub is_initializer: bool,
+    /// The class declaring the method
+    pub class: Option<Symbol>,
+    /// A method declared without parameters, called when the property is read
+    pub is_getter: bool,
+    /// Declared with `fun*`, calling it creates a generator
+    pub is_generator: bool,
 }
 
 impl std::fmt::Display for Function {
@@ -25,17 +34,42 @@ impl std::fmt::Display for Function {
     }
 }
 
+impl Function {
+    /// The function with its body folded, `None` if nothing changed
+    pub fn fold(&self) -> Option<Function> {
+        let body = self.body.fold()?;
+        Some(Function {
+            body: Rc::new(body),
+            ..self.clone()
+        })
+    }
+
+    pub fn profile_id(&self) -> FunctionId {
+        FunctionId {
+            class: self.class,
+            name: self.name.symbol(),
+            line: self.name.line,
+        }
+    }
+}
+
 impl Stmt for Function {
     fn interpret(&self) -> Result<(), crate::error::Error> {
         unsafe {
-            let function = Value::Fun(Rc::new(self.clone()), None, None);
+            crate::Environment::capture(&crate::ENVIRONMENT);
+            let function = Value::Fun(Rc::new(self.clone()), crate::ENVIRONMENT.clone());
 
             crate::ENVIRONMENT
                 .borrow_mut()
-                .define(self.name.lexeme.clone(), Box::new(function));
+                .define(self.name.symbol(), function);
         }
         Ok(())
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        self.fold()
+            .map(|function| Rc::new(function) as Rc<dyn Stmt>)
+    }
 }
 
 impl Resolver for Function {
@@ -52,6 +86,11 @@ pub fn resolve_function(
     function_type: FunctionType,
 ) -> Result<(), Error> {
     let enclosing_function_type = scopes.get_current_function();
+    let function_type = if function.is_generator {
+        FunctionType::Generator
+    } else {
+        function_type
+    };
     scopes.set_current_function(Some(function_type));
     scopes.begin_scope();
     for param in &function.params {
@@ -73,8 +112,10 @@ fn resolve_statements(statements: Vec<Rc<dyn Stmt>>, scopes: &mut Scopes) -> Res
 
 #[derive(Clone)]
 pub struct Builtin {
+    /// The number of arguments, or the minimum number of arguments if the builtin is variadic
     pub arity: usize,
-    pub call: fn(Vec<Box<Value>>) -> Result<Box<Value>, Error>,
+    pub variadic: bool,
+    pub call: fn(Vec<Value>) -> Result<Value, Error>,
 }
 
 impl std::fmt::Debug for Builtin {
@@ -84,7 +125,17 @@ impl std::fmt::Debug for Builtin {
 }
 
 impl Builtin {
-    pub fn call(&self, args: Vec<Box<Value>>) -> Result<Box<Value>, Error> {
+    pub fn call(&self, args: Vec<Value>) -> Result<Value, Error> {
         (self.call)(args)
     }
 }
+
+impl Compile for Function {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        compiler.declare_variable(&self.name)?;
+        // a local function can refer to itself
+        compiler.mark_initialized();
+        compiler.function(self, FunctionType::Function)?;
+        compiler.define_variable(&self.name)
+    }
+}
@@ -1,4 +1,7 @@
+use crate::ast::optimizer::{constant_condition, fold, fold_stmt, pruned};
 use crate::ast::{Expr, Resolver, Stmt};
+use crate::generator::{is_yield, Resume};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::{Error, Scopes, Value};
 use std::rc::Rc;
 
@@ -26,9 +29,9 @@ impl std::fmt::Display for IfExpr {
 impl Stmt for IfExpr {
     fn interpret(&self) -> Result<(), crate::error::Error> {
         let condition = self.condition.eval()?;
-        if let Value::Boolean(true) = condition.as_ref() {
+        if let Value::Boolean(true) = &condition {
             self.then_branch.interpret()
-        } else if let Value::Boolean(false) = condition.as_ref() {
+        } else if let Value::Boolean(false) = &condition {
             if let Some(stmt) = &self.else_branch {
                 stmt.interpret()
             } else {
@@ -42,6 +45,63 @@ impl Stmt for IfExpr {
             ))
         }
     }
+
+    fn generate(&self, resume: &mut Vec<Resume>) -> Result<(), Error> {
+        let then_branch = match resume.pop() {
+            Some(Resume::If { then_branch }) => then_branch,
+            None => match self.condition.eval()? {
+                Value::Boolean(condition) => condition,
+                _ => {
+                    return Err(Error::new(
+                        0,
+                        self.condition.to_string(),
+                        "Expect boolean condition".to_string(),
+                    ))
+                }
+            },
+            Some(_) => unreachable!(),
+        };
+        let branch = if then_branch {
+            &self.then_branch
+        } else {
+            match &self.else_branch {
+                Some(stmt) => stmt,
+                None => return Ok(()),
+            }
+        };
+        let result = branch.generate(resume);
+        if is_yield(&result) {
+            resume.push(Resume::If { then_branch });
+        }
+        result
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        let condition = fold(&self.condition);
+        match constant_condition(&condition) {
+            Some(true) => return Some(fold_stmt(&self.then_branch)),
+            Some(false) => {
+                return Some(self.else_branch.as_ref().map_or_else(pruned, fold_stmt));
+            }
+            None => {}
+        }
+        let then_branch = fold_stmt(&self.then_branch);
+        let else_branch = self.else_branch.as_ref().map(fold_stmt);
+        if Rc::ptr_eq(&condition, &self.condition)
+            && Rc::ptr_eq(&then_branch, &self.then_branch)
+            && else_branch
+                .iter()
+                .zip(&self.else_branch)
+                .all(|(folded, stmt)| Rc::ptr_eq(folded, stmt))
+        {
+            return None;
+        }
+        Some(Rc::new(IfExpr {
+            condition,
+            then_branch,
+            else_branch,
+        }))
+    }
 }
 
 impl Resolver for IfExpr {
@@ -54,3 +114,19 @@ impl Resolver for IfExpr {
         Ok(())
     }
 }
+
+impl Compile for IfExpr {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        self.condition.compile(compiler)?;
+        let then_jump = compiler.emit_jump(OpCode::JumpIfFalse);
+        self.then_branch.compile(compiler)?;
+        if let Some(stmt) = &self.else_branch {
+            let else_jump = compiler.emit_jump(OpCode::Jump);
+            compiler.patch_jump(then_jump)?;
+            stmt.compile(compiler)?;
+            compiler.patch_jump(else_jump)
+        } else {
+            compiler.patch_jump(then_jump)
+        }
+    }
+}
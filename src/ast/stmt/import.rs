@@ -0,0 +1,48 @@
+use crate::ast::{Resolver, Stmt};
+use crate::interner::Symbol;
+use crate::vm::{Compile, Compiler, OpCode};
+use crate::{Error, Scopes, Token};
+use std::rc::Rc;
+
+/// `import "path" as name;`, declares `name` holding the namespace of the module
+#[derive(Debug)]
+pub struct Import {
+    pub keyword: Token,
+    pub path: String,
+    pub name: Token,
+}
+
+impl std::fmt::Display for Import {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<import>({:?} {})", self.path, self.name)
+    }
+}
+
+impl Stmt for Import {
+    fn interpret(&self) -> Result<(), Error> {
+        let namespace = crate::module::import(&self.path, self.keyword.line)?;
+        unsafe {
+            crate::ENVIRONMENT
+                .borrow_mut()
+                .define(self.name.symbol(), namespace);
+        }
+        Ok(())
+    }
+}
+
+impl Resolver for Import {
+    fn resolve(self: Rc<Self>, scopes: &mut Scopes) -> Result<(), Error> {
+        scopes.declare(self.name.clone())?;
+        scopes.define(self.name.clone());
+        Ok(())
+    }
+}
+
+impl Compile for Import {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        compiler.declare_variable(&self.name)?;
+        compiler.set_line(self.keyword.line);
+        compiler.emit(OpCode::Import(Symbol::intern(&self.path)));
+        compiler.define_variable(&self.name)
+    }
+}
@@ -1,23 +1,74 @@
+use crate::ast::optimizer::fold_all;
 use crate::ast::{Expr, Resolver, Stmt};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::{Error, Scopes};
-use rlox_macro::Expr;
 use std::rc::Rc;
 
-#[derive(Expr, Debug)]
+#[derive(Debug)]
 pub struct Print {
-    pub expression: Rc<dyn Expr>,
+    pub expressions: Vec<Rc<dyn Expr>>,
+    /// `eprint` writes to stderr instead of stdout
+    pub to_stderr: bool,
+}
+
+impl std::fmt::Display for Print {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut expressions = String::new();
+        for expr in &self.expressions {
+            expressions.push_str(&format!("{} ", expr));
+        }
+        if self.to_stderr {
+            write!(f, "<eprint>({})", expressions)
+        } else {
+            write!(f, "<print>({})", expressions)
+        }
+    }
 }
 
 impl Stmt for Print {
     fn interpret(&self) -> Result<(), crate::error::Error> {
-        let value = self.expression.eval()?;
-        println!("{}", value);
+        let mut values = Vec::new();
+        for expr in &self.expressions {
+            values.push(expr.eval()?);
+        }
+        if self.to_stderr {
+            eprintln!("{}", crate::join_values(&values)?);
+        } else {
+            println!("{}", crate::join_values(&values)?);
+        }
         Ok(())
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        let expressions = fold_all(&self.expressions)?;
+        Some(Rc::new(Print {
+            expressions,
+            to_stderr: self.to_stderr,
+        }))
+    }
 }
 
 impl Resolver for Print {
     fn resolve(self: Rc<Self>, scopes: &mut Scopes) -> Result<(), Error> {
-        self.expression.clone().resolve(scopes)
+        for expr in &self.expressions {
+            expr.clone().resolve(scopes)?;
+        }
+        Ok(())
+    }
+}
+
+impl Compile for Print {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        for expr in &self.expressions {
+            expr.compile(compiler)?;
+        }
+        let count = u8::try_from(self.expressions.len())
+            .map_err(|_| Error::new(0, "".to_string(), "Too many values to print".to_string()))?;
+        if self.to_stderr {
+            compiler.emit(OpCode::EPrint(count));
+        } else {
+            compiler.emit(OpCode::Print(count));
+        }
+        Ok(())
     }
 }
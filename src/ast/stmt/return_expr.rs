@@ -1,11 +1,16 @@
+use crate::ast::value::LoxCallable;
 use crate::ast::{Expr, Resolver, Stmt};
-use crate::{Error, Scopes, Token};
+use crate::vm::{Compile, Compiler, OpCode};
+use crate::{Error, Scopes, Token, Value};
+use std::cell::Cell;
 use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct ReturnExpr {
     pub keyword: Token,
     pub value: Option<Rc<dyn Expr>>,
+    /// Set by the resolver when the value is a call, which then replaces the call returning
+    pub tail_call: Cell<bool>,
 }
 
 impl std::fmt::Display for ReturnExpr {
@@ -20,25 +25,47 @@ impl std::fmt::Display for ReturnExpr {
 
 impl Stmt for ReturnExpr {
     fn interpret(&self) -> Result<(), crate::error::Error> {
-        if let Some(expr) = &self.value {
-            let value = expr.eval()?;
-            unsafe {
-                crate::ENVIRONMENT
-                    .borrow_mut()
-                    .define("return".to_string(), value);
-            }
-        } else {
-            unsafe {
-                crate::ENVIRONMENT
-                    .borrow_mut()
-                    .define("return".to_string(), Box::new(crate::Value::Nil));
+        let value = match &self.value {
+            Some(expr) if self.tail_call.get() => {
+                let (callee, arguments) = expr.as_call().unwrap().prepare()?;
+                if let Value::Fun(_, _) = callee {
+                    // the call returning runs it in its place, so the stack doesn't grow
+                    unsafe {
+                        crate::TAIL_CALL = Some((callee, arguments));
+                    }
+                    return Err(Self::unwind());
+                }
+                callee.call(arguments)?
             }
+            Some(expr) => expr.eval()?,
+            None => Value::Nil,
+        };
+        // the call picks the value up when the error reaches it
+        unsafe {
+            crate::RETURN_VALUE = Some(value);
         }
-        Err(Error {
+        Err(Self::unwind())
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        let value = self.value.as_ref()?.optimize()?;
+        Some(Rc::new(ReturnExpr {
+            keyword: self.keyword.clone(),
+            tail_call: Cell::new(self.tail_call.get() && value.as_call().is_some()),
+            value: Some(value),
+        }))
+    }
+}
+
+impl ReturnExpr {
+    /// The error unwinding to the call
+    fn unwind() -> Error {
+        Error {
             line: 0,
+            column: 0,
             loc: "".to_string(),
             message: "return".to_string(),
-        })
+        }
     }
 }
 
@@ -56,9 +83,37 @@ impl Resolver for ReturnExpr {
                     "Can't return a value from an initializer".to_string(),
                 ));
             }
+        } else if let Some(crate::FunctionType::Generator) = scopes.get_current_function() {
+            if self.value.is_some() {
+                return Err(Error::report(
+                    self.keyword.clone(),
+                    "Can't return a value from a generator".to_string(),
+                ));
+            }
         }
         if let Some(expr) = &self.value {
             expr.clone().resolve(scopes)?;
+            self.tail_call.set(expr.as_call().is_some());
+        }
+        Ok(())
+    }
+}
+
+impl Compile for ReturnExpr {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        compiler.set_line(self.keyword.line);
+        if let Some(call) = self.value.as_ref().and_then(|expr| expr.as_call()) {
+            if self.tail_call.get() {
+                call.compile_tail(compiler)?;
+            } else {
+                call.compile(compiler)?;
+            }
+            compiler.emit(OpCode::Return);
+        } else if let Some(expr) = &self.value {
+            expr.compile(compiler)?;
+            compiler.emit(OpCode::Return);
+        } else {
+            compiler.emit_return();
         }
         Ok(())
     }
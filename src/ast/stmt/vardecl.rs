@@ -1,4 +1,5 @@
 use crate::ast::{Expr, Resolver, Stmt};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::{Error, Scopes, Token, Value};
 use std::rc::Rc;
 
@@ -7,6 +8,8 @@ use std::rc::Rc;
 pub struct VarDecl {
     pub name: Token,
     pub initializer: Option<Rc<dyn Expr>>,
+    /// Declared with `const` or `let`, it can't be assigned
+    pub constant: bool,
 }
 
 impl std::fmt::Display for VarDecl {
@@ -15,7 +18,8 @@ impl std::fmt::Display for VarDecl {
             Some(expr) => format!(" = {}", expr),
             None => "Nil".to_string(),
         };
-        write!(f, "<v>({} {initializer})", self.name)
+        let tag = if self.constant { "c" } else { "v" };
+        write!(f, "<{tag}>({} {initializer})", self.name)
     }
 }
 
@@ -23,20 +27,34 @@ impl Stmt for VarDecl {
     fn interpret(&self) -> Result<(), crate::error::Error> {
         let value = match &self.initializer {
             Some(expr) => expr.eval()?,
-            None => Box::new(Value::Nil),
+            None => Value::Nil,
         };
-        unsafe {
-            crate::ENVIRONMENT
-                .borrow_mut()
-                .define(self.name.lexeme.clone(), value);
+        let mut environment = unsafe { crate::ENVIRONMENT.borrow_mut() };
+        if self.constant {
+            environment.define_constant(self.name.symbol(), value);
+        } else {
+            environment.define(self.name.symbol(), value);
         }
         Ok(())
     }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        let initializer = self.initializer.as_ref()?.optimize()?;
+        Some(Rc::new(VarDecl {
+            name: self.name.clone(),
+            initializer: Some(initializer),
+            constant: self.constant,
+        }))
+    }
 }
 
 impl Resolver for VarDecl {
     fn resolve(self: Rc<Self>, scopes: &mut Scopes) -> Result<(), Error> {
-        scopes.declare(self.name.clone())?;
+        if self.constant {
+            scopes.declare_constant(self.name.clone())?;
+        } else {
+            scopes.declare(self.name.clone())?;
+        }
         if let Some(expr) = &self.initializer {
             expr.clone().resolve(scopes)?;
         }
@@ -44,3 +62,20 @@ impl Resolver for VarDecl {
         Ok(())
     }
 }
+
+impl Compile for VarDecl {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        compiler.declare_variable(&self.name)?;
+        match &self.initializer {
+            Some(expr) => expr.compile(compiler)?,
+            None => {
+                compiler.emit(OpCode::Nil);
+            }
+        }
+        if self.constant {
+            compiler.define_constant(&self.name)
+        } else {
+            compiler.define_variable(&self.name)
+        }
+    }
+}
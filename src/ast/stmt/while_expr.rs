@@ -1,4 +1,7 @@
+use crate::ast::optimizer::{constant_condition, fold, fold_stmt, pruned};
 use crate::ast::{Expr, Resolver, Stmt};
+use crate::generator::{is_yield, Resume};
+use crate::vm::{Compile, Compiler, OpCode};
 use crate::{Error, Scopes, Value};
 use std::rc::Rc;
 
@@ -16,11 +19,48 @@ impl std::fmt::Display for WhileExpr {
 
 impl Stmt for WhileExpr {
     fn interpret(&self) -> Result<(), crate::error::Error> {
-        while let Value::Boolean(true) = self.condition.eval()?.as_ref() {
+        while let Value::Boolean(true) = &self.condition.eval()? {
+            crate::interrupt::check()?;
+            crate::limits::step()?;
             self.body.interpret()?;
         }
         Ok(())
     }
+
+    fn generate(&self, resume: &mut Vec<Resume>) -> Result<(), Error> {
+        let mut resuming = match resume.pop() {
+            Some(Resume::While) => true,
+            None => false,
+            Some(_) => unreachable!(),
+        };
+        loop {
+            if !resuming {
+                if !matches!(self.condition.eval()?, Value::Boolean(true)) {
+                    return Ok(());
+                }
+                crate::interrupt::check()?;
+                crate::limits::step()?;
+            }
+            resuming = false;
+            let result = self.body.generate(resume);
+            if is_yield(&result) {
+                resume.push(Resume::While);
+            }
+            result?;
+        }
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        let condition = fold(&self.condition);
+        if constant_condition(&condition) == Some(false) {
+            return Some(pruned());
+        }
+        let body = fold_stmt(&self.body);
+        if Rc::ptr_eq(&condition, &self.condition) && Rc::ptr_eq(&body, &self.body) {
+            return None;
+        }
+        Some(Rc::new(WhileExpr { condition, body }))
+    }
 }
 
 impl Resolver for WhileExpr {
@@ -29,3 +69,14 @@ impl Resolver for WhileExpr {
         self.body.clone().resolve(scopes)
     }
 }
+
+impl Compile for WhileExpr {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        let loop_start = compiler.loop_start();
+        self.condition.compile(compiler)?;
+        let exit_jump = compiler.emit_jump(OpCode::JumpUnlessTrue);
+        self.body.compile(compiler)?;
+        compiler.emit_loop(loop_start)?;
+        compiler.patch_jump(exit_jump)
+    }
+}
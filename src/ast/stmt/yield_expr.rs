@@ -0,0 +1,88 @@
+use crate::ast::{Expr, Resolver, Stmt};
+use crate::generator::Resume;
+use crate::vm::{Compile, Compiler, OpCode};
+use crate::{Error, FunctionType, Scopes, Token, Value};
+use std::rc::Rc;
+
+/// `yield value;`, suspends the generator running the body with the value as its next element
+#[derive(Debug)]
+pub struct YieldExpr {
+    pub keyword: Token,
+    pub value: Option<Rc<dyn Expr>>,
+}
+
+impl std::fmt::Display for YieldExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match &self.value {
+            Some(expr) => format!("{}", expr),
+            None => "Nil".to_string(),
+        };
+        write!(f, "<y>({} {})", self.keyword, value)
+    }
+}
+
+impl Stmt for YieldExpr {
+    fn interpret(&self) -> Result<(), Error> {
+        self.generate(&mut Vec::new())
+    }
+
+    fn generate(&self, resume: &mut Vec<Resume>) -> Result<(), Error> {
+        if let Some(Resume::Yield) = resume.last() {
+            // the generator continues after this yield
+            resume.pop();
+            return Ok(());
+        }
+        let value = match &self.value {
+            Some(expr) => expr.eval()?,
+            None => Value::Nil,
+        };
+        // the generator picks the value up when the error reaches it
+        unsafe {
+            crate::YIELD_VALUE = Some(value);
+        }
+        resume.push(Resume::Yield);
+        Err(Error {
+            line: 0,
+            column: 0,
+            loc: "".to_string(),
+            message: "yield".to_string(),
+        })
+    }
+
+    fn optimize(&self) -> Option<Rc<dyn Stmt>> {
+        let value = self.value.as_ref()?.optimize()?;
+        Some(Rc::new(YieldExpr {
+            keyword: self.keyword.clone(),
+            value: Some(value),
+        }))
+    }
+}
+
+impl Resolver for YieldExpr {
+    fn resolve(self: Rc<Self>, scopes: &mut Scopes) -> Result<(), Error> {
+        if !matches!(scopes.get_current_function(), Some(FunctionType::Generator)) {
+            return Err(Error::report(
+                self.keyword.clone(),
+                "Can't yield outside a generator".to_string(),
+            ));
+        }
+        if let Some(expr) = &self.value {
+            expr.clone().resolve(scopes)?;
+        }
+        Ok(())
+    }
+}
+
+impl Compile for YieldExpr {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error> {
+        match &self.value {
+            Some(expr) => expr.compile(compiler)?,
+            None => {
+                compiler.emit(OpCode::Nil);
+            }
+        }
+        compiler.set_line(self.keyword.line);
+        compiler.emit(OpCode::Yield);
+        Ok(())
+    }
+}
@@ -1,57 +1,96 @@
-use crate::ast::stmt::{Function, Instance};
-use crate::{Builtin, Environment, Error, Token, TokenType};
-use once_cell::sync::Lazy;
+use crate::ast::stmt::{Function, Instance, LoxClass};
+use crate::gc::{trace_value, Trace};
+use crate::generator::{Generator, GeneratorState};
+use crate::interner::Symbol;
+use crate::module::Namespace;
+use crate::vm::Closure;
+use crate::{Builtin, Environment, Error, Token, TokenType, RETURN_VALUE, TAIL_CALL};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
 pub trait LoxCallable {
-    fn call(&self, arguments: Vec<Box<Value>>) -> Result<Box<Value>, Error>;
+    fn call(&self, arguments: Vec<Value>) -> Result<Value, Error>;
     fn arity(&self) -> usize;
+    fn is_variadic(&self) -> bool;
     fn is_callable(&self) -> bool;
 }
 
-#[derive(Clone, Debug)]
+/// A runtime value, scalars are stored inline and heap values behind `Rc`
+/// so copying a value never copies the data it refers to. Arrays are still values, an array
+/// is copied when an element is assigned while another value shares it, see `set_element`.
+#[derive(Clone)]
 pub enum Value {
     Number(f64),
-    String(String),
+    String(Rc<str>),
     Boolean(bool),
-    Fun(
-        Rc<Function>,
-        Option<Rc<RefCell<Instance>>>,
-        Option<Box<Value>>,
-    ),
+    /// A function, with the environment it closes over
+    Fun(Rc<Function>, Rc<RefCell<Environment>>),
     Builtin(Rc<Builtin>),
-    Class {
-        class: String,
-        methods: HashMap<String, Box<Value>>,
-        super_class: Option<Box<Value>>,
-    },
+    /// A builtin method of a value that isn't an instance, with that value
+    BuiltinMethod(&'static Builtin, Box<Value>),
+    /// Function compiled for the vm, with the instance it is bound to
+    Closure(Rc<Closure>, Option<Rc<RefCell<Instance>>>),
+    Class(Rc<LoxClass>),
     Instance(Rc<RefCell<Instance>>),
-    Array(Vec<Box<Value>>),
+    Array(Rc<RefCell<Vec<Value>>>),
     ArrayObject {
-        array: Vec<Box<Value>>,
+        array: Rc<RefCell<Vec<Value>>>,
         index: usize,
     },
+    Range(Rc<Range>),
+    Generator(Rc<Generator>),
+    /// The members of an imported module
+    Namespace(Rc<Namespace>),
     Nil,
 }
 
+/// The numbers of `range(start, end, step)`, computed as they are iterated instead of stored
+#[derive(Debug, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+impl Range {
+    /// The number at `position`, `None` past the end
+    pub fn get(&self, position: usize) -> Option<f64> {
+        let n = self.start + position as f64 * self.step;
+        if (self.step > 0.0 && n < self.end) || (self.step < 0.0 && n > self.end) {
+            Some(n)
+        } else {
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        ((self.end - self.start) / self.step).ceil().max(0.0) as usize
+    }
+}
+
 impl Value {
+    pub fn array(values: Vec<Value>) -> Self {
+        Value::Array(crate::gc::track(Rc::new(RefCell::new(values))))
+    }
     pub fn bind(&mut self, instance: Rc<RefCell<Instance>>) {
         match self {
-            Value::Fun(fun, _, super_) => {
-                *self = Value::Fun(fun.clone(), Some(instance), super_.clone());
+            Value::Fun(fun, closure) => {
+                let mut environment = Environment::new(Some(closure.clone()));
+                environment.define(Symbol::THIS, Value::Instance(instance));
+                let environment = Rc::new(RefCell::new(environment));
+                Environment::capture(&environment);
+                *self = Value::Fun(fun.clone(), environment);
+            }
+            Value::Closure(closure, _) => {
+                *self = Value::Closure(closure.clone(), Some(instance));
             }
             _ => {}
         }
     }
     pub fn is_class(&self) -> bool {
         match self {
-            Value::Class {
-                class: _,
-                methods: _,
-                super_class: _,
-            } => true,
+            Value::Class(_) => true,
             _ => false,
         }
     }
@@ -61,182 +100,496 @@ impl Value {
             _ => false,
         }
     }
-    pub fn get_method(&self, name: &str) -> Option<Box<Value>> {
+    /// Store the value in the element at `indeces` of nested arrays, instances are indexed
+    /// with their `__index__` and `__setindex__` methods. Arrays are values: an array shared
+    /// with another value is copied before it is changed, so only the one held by `self` is.
+    pub fn set_element(
+        &mut self,
+        indeces: &[Value],
+        value: Value,
+        name: &Token,
+    ) -> Result<(), Error> {
+        let error = |message: &str| Error::new(name.line, name.lexeme.clone(), message.to_string());
+        let position = |index: &Value| match index {
+            Value::Number(i) if i.fract() == 0f64 && *i >= 0f64 && i.is_finite() => Ok(*i as usize),
+            Value::Number(_) => Err(error("Index must be non-negative integer")),
+            _ => Err(error("Index must be a number")),
+        };
+        let (index, rest) = indeces.split_first().ok_or_else(|| error("Expect index"))?;
         match self {
-            Value::Class {
-                class: _,
-                methods,
-                super_class,
-            } => {
-                if methods.contains_key(name) {
-                    methods.get(name).cloned()
-                } else if let Some(super_class) = super_class {
-                    super_class.get_method(name)
+            Value::Array(array) => {
+                let position = position(index)?;
+                if Rc::strong_count(array) > 1 {
+                    let copy = array.borrow().clone();
+                    *self = Value::array(copy);
+                }
+                let Value::Array(array) = self else {
+                    unreachable!()
+                };
+                let mut elements = array.borrow_mut();
+                let element = elements
+                    .get_mut(position)
+                    .ok_or_else(|| error("Index out of bounds"))?;
+                if rest.is_empty() {
+                    *element = value;
+                    Ok(())
                 } else {
-                    None
+                    element.set_element(rest, value, name)
                 }
             }
+            Value::Instance(_) => {
+                // the element is a value too, it is changed and stored back
+                let value = if rest.is_empty() {
+                    value
+                } else {
+                    let mut element = self
+                        .call_special(Symbol::INDEX, vec![index.clone()])
+                        .unwrap_or_else(|| Err(error("Not an array")))?;
+                    element.set_element(rest, value, name)?;
+                    element
+                };
+                self.call_special(Symbol::SETINDEX, vec![index.clone(), value])
+                    .unwrap_or_else(|| Err(error("Not an array")))
+                    .map(|_| ())
+            }
+            _ => Err(error("Not an array")),
+        }
+    }
+    /// `object.name[i]... = value`, the field is taken out of the object while its element is
+    /// set, so the array it holds isn't shared and is changed in place
+    pub fn set_field_element(
+        &self,
+        name: &Token,
+        indeces: &[Value],
+        value: Value,
+    ) -> Result<(), Error> {
+        let undefined = || Error::new(name.line, name.lexeme.clone(), "Undefined property".into());
+        let symbol = name.symbol();
+        match self {
+            Value::Instance(instance) => {
+                let field = instance.borrow_mut().fields.remove(&symbol);
+                let mut field = field.ok_or_else(undefined)?;
+                let result = field.set_element(indeces, value, name);
+                instance.borrow_mut().set(symbol, field);
+                result
+            }
+            Value::Class(class) => {
+                let mut field = class.take_static(symbol).ok_or_else(undefined)?;
+                let result = field.set_element(indeces, value, name);
+                class.set_static(symbol, field);
+                result
+            }
+            Value::Namespace(namespace) => {
+                let mut member = namespace.get(symbol).ok_or_else(undefined)?;
+                member.set_element(indeces, value, name)?;
+                namespace
+                    .set(symbol, member)
+                    .map_err(|message| Error::new(name.line, name.lexeme.clone(), message.into()))
+            }
+            _ => Err(Error::new(
+                name.line,
+                name.lexeme.clone(),
+                "Only instance have fields".to_string(),
+            )),
+        }
+    }
+    /// The builtin method `name` of a value that isn't an instance, like the `next` of a
+    /// generator, bound to the value
+    pub fn builtin_method(&self, name: Symbol) -> Option<Value> {
+        let builtin = match (self, name) {
+            (Value::Generator(_), Symbol::NEXT) => &crate::builtins::GENERATOR_NEXT,
+            _ => return None,
+        };
+        Some(Value::BuiltinMethod(builtin, Box::new(self.clone())))
+    }
+    /// Call the special method `name` of an instance, `None` if the value isn't an instance or
+    /// has no such method
+    pub fn call_special(
+        &self,
+        name: Symbol,
+        arguments: Vec<Value>,
+    ) -> Option<Result<Value, Error>> {
+        let Value::Instance(instance) = self else {
+            return None;
+        };
+        let method = instance.borrow().get(name, instance.clone())?;
+        if method.arity() != arguments.len() {
+            return Some(Err(Error::new(
+                0,
+                name.to_string(),
+                format!(
+                    "Expected {} arguments but got {}.",
+                    method.arity(),
+                    arguments.len()
+                ),
+            )));
+        }
+        Some(method.call(arguments))
+    }
+    /// The result of an operator overloaded by the special method of the left operand
+    #[inline]
+    pub fn operator_method(&self, name: Symbol, right: &Value) -> Option<Result<Value, Error>> {
+        match self {
+            Value::Instance(_) => self.call_special(name, vec![right.clone()]),
+            _ => None,
+        }
+    }
+    /// `self == other` of the language, with the `__eq__` method of an instance, and of the
+    /// instances in arrays. Arrays are copied on write so they can't contain themselves, only an
+    /// `__eq__` can recurse without end and it runs into the call depth limit
+    pub fn equals(&self, other: &Value) -> Result<bool, Error> {
+        if let Some(result) = self.operator_method(Symbol::EQ, other) {
+            return result?.special_boolean(Symbol::EQ);
+        }
+        match (self, other) {
+            (Value::Array(a), Value::Array(b)) if !Rc::ptr_eq(a, b) => {
+                if a.borrow().len() != b.borrow().len() {
+                    return Ok(false);
+                }
+                // `__eq__` may change the arrays, they aren't borrowed while it runs
+                let mut i = 0;
+                loop {
+                    let (x, y) = (a.borrow().get(i).cloned(), b.borrow().get(i).cloned());
+                    match (x, y) {
+                        (Some(x), Some(y)) if x.equals(&y)? => i += 1,
+                        (None, None) => return Ok(true),
+                        _ => return Ok(false),
+                    }
+                }
+            }
+            _ => Ok(self == other),
+        }
+    }
+    /// `self < other` with `__lt__`, if `self` has it. The other comparisons swap the operands
+    /// or negate the result
+    #[inline]
+    pub fn less_method(&self, other: &Value) -> Option<Result<bool, Error>> {
+        self.operator_method(Symbol::LT, other)
+            .map(|result| result.and_then(|value| value.special_boolean(Symbol::LT)))
+    }
+    fn special_boolean(&self, name: Symbol) -> Result<bool, Error> {
+        match self {
+            Value::Boolean(b) => Ok(*b),
+            _ => Err(Error::new(
+                0,
+                name.to_string(),
+                "Method must return a boolean".to_string(),
+            )),
+        }
+    }
+    /// The value a `for in` loop walks with `next_element`. Arrays, strings, ranges and
+    /// generators walk themselves. An instance walks the result of its `iter()` method, itself
+    /// if it has a `next()` method, and otherwise the sorted names of its fields. `call` calls
+    /// a bound method, so the vm can run it on its own stack.
+    pub fn iterable(
+        &self,
+        name: &Token,
+        call: &mut dyn FnMut(Value) -> Result<Value, Error>,
+    ) -> Result<Value, Error> {
+        match self {
+            Value::Array(_) | Value::String(_) | Value::Range(_) | Value::Generator(_) => {
+                Ok(self.clone())
+            }
+            Value::Instance(instance) => {
+                if let Some(iterator) = self.iterator_method(Symbol::ITER, name, call) {
+                    return iterator;
+                }
+                let class = instance.borrow().class.clone();
+                if class.find_method(Symbol::NEXT).is_some() {
+                    return Ok(self.clone());
+                }
+                let mut names = instance
+                    .borrow()
+                    .fields
+                    .keys()
+                    .map(|name| name.to_string())
+                    .collect::<Vec<_>>();
+                names.sort();
+                Ok(Value::array(
+                    names
+                        .into_iter()
+                        .map(|name| Value::String(name.into()))
+                        .collect(),
+                ))
+            }
+            _ => Err(Error::new(
+                name.line,
+                name.lexeme.clone(),
+                "Value is not iterable".to_string(),
+            )),
+        }
+    }
+    /// The element of an iterable at `position`, which moves past it. `None` at the end, which
+    /// for an iterator is when its `next()` method returns nil.
+    pub fn next_element(
+        &self,
+        position: &mut usize,
+        name: &Token,
+        call: &mut dyn FnMut(Value) -> Result<Value, Error>,
+    ) -> Result<Option<Value>, Error> {
+        let element = match self {
+            Value::Array(array) => array.borrow().get(*position).cloned(),
+            // the position in a string counts bytes, so the next character is found in place
+            Value::String(s) => s
+                .get(*position..)
+                .and_then(|rest| rest.chars().next())
+                .map(|c| {
+                    *position += c.len_utf8() - 1;
+                    Value::String(c.to_string().into())
+                }),
+            Value::Range(range) => range.get(*position).map(Value::Number),
+            Value::Generator(generator) => return generator.resume(),
+            _ => {
+                let element = self
+                    .iterator_method(Symbol::NEXT, name, call)
+                    .unwrap_or_else(|| {
+                        Err(Error::new(
+                            name.line,
+                            name.lexeme.clone(),
+                            "Iterator must have a next() method".to_string(),
+                        ))
+                    })?;
+                return Ok(if element.is_nil() {
+                    None
+                } else {
+                    Some(element)
+                });
+            }
+        };
+        *position += 1;
+        Ok(element)
+    }
+    /// The result of the method `name` of the class of an instance, which takes no arguments
+    fn iterator_method(
+        &self,
+        name: Symbol,
+        token: &Token,
+        call: &mut dyn FnMut(Value) -> Result<Value, Error>,
+    ) -> Option<Result<Value, Error>> {
+        let Value::Instance(instance) = self else {
+            return None;
+        };
+        let mut method = instance.borrow().class.find_method(name)?;
+        if method.arity() != 0 {
+            return Some(Err(Error::new(
+                token.line,
+                name.to_string(),
+                format!("Expected 0 arguments but got {}.", method.arity()),
+            )));
+        }
+        method.bind(instance.clone());
+        Some(call(method))
+    }
+    /// The value as a string, with the `__str__` method of an instance if it has one
+    pub fn stringify(&self) -> Result<String, Error> {
+        match self.call_special(Symbol::STR, Vec::new()) {
+            Some(value) => Ok(value?.to_string()),
+            None => Ok(self.to_string()),
+        }
+    }
+    /// The bound `__call__` method of an instance
+    fn call_method(&self) -> Option<Value> {
+        match self {
+            Value::Instance(instance) => instance.borrow().get(Symbol::CALL, instance.clone()),
+            _ => None,
+        }
+    }
+    pub fn get_method(&self, name: Symbol) -> Option<Value> {
+        match self {
+            Value::Class(class) => class.find_method(name),
             _ => None,
         }
     }
+    /// Whether the value is a getter, called when its property is read
+    pub fn is_getter(&self) -> bool {
+        match self {
+            Value::Fun(fun, _) => fun.is_getter,
+            Value::Closure(closure, _) => closure.function.is_getter,
+            _ => false,
+        }
+    }
+    /// The name of the type of the value, returned by `type`
+    pub fn type_of(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Boolean(_) => "boolean",
+            Value::Fun(_, _)
+            | Value::Builtin(_)
+            | Value::BuiltinMethod(_, _)
+            | Value::Closure(_, _) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::Array(_) | Value::ArrayObject { .. } => "array",
+            Value::Range(_) => "range",
+            Value::Generator(_) => "generator",
+            Value::Namespace(_) => "module",
+            Value::Nil => "nil",
+        }
+    }
+    /// Whether the value is an instance of the class or of one of its subclasses
+    pub fn instance_of(&self, class: &Rc<LoxClass>) -> bool {
+        match self {
+            Value::Instance(instance) => instance.borrow().class.inherits(class),
+            _ => false,
+        }
+    }
 }
 
 impl LoxCallable for Value {
     fn arity(&self) -> usize {
-        if let Value::Fun(fun, _, _) = self {
+        if let Value::Fun(fun, _) = self {
             fun.params.len()
-        } else if let Value::Class {
-            class: _,
-            methods,
-            super_class: _,
-        } = self
-        {
-            let initializer = methods.get("init");
-            if let Some(initializer) = initializer {
+        } else if let Value::Class(class) = self {
+            if let Some(initializer) = class.methods.get(&Symbol::INIT).cloned() {
                 initializer.arity()
             } else {
                 0
             }
         } else if let Value::Builtin(builtin) = self {
             builtin.arity
+        } else if let Value::BuiltinMethod(builtin, _) = self {
+            builtin.arity
+        } else if let Value::Closure(closure, _) = self {
+            closure.function.arity()
+        } else if let Some(method) = self.call_method() {
+            method.arity()
         } else {
             0
         }
     }
 
-    fn call(&self, arguments: Vec<Box<Value>>) -> Result<Box<Value>, Error> {
-        if let Value::Fun(fun, this, super_) = self {
-            unsafe {
-                // create a new environment for the function
-                let previous = Rc::new(RefCell::new(crate::ENVIRONMENT.borrow().clone()));
-
-                // bind super to the environment
-                if let Some(super_) = super_ {
-                    crate::ENVIRONMENT =
-                        Lazy::new(|| Rc::new(RefCell::new(Environment::new(None))));
-                    crate::ENVIRONMENT
-                        .borrow_mut()
-                        .set_enclosing(Some(previous.clone()));
-                    crate::ENVIRONMENT
-                        .borrow_mut()
-                        .define("super".to_string(), super_.clone());
+    fn call(&self, arguments: Vec<Value>) -> Result<Value, Error> {
+        if let Value::Fun(_, _) = self {
+            let mut callee = self.clone();
+            let mut arguments = arguments;
+            // a tail call runs here in place of the function returning it
+            loop {
+                let Value::Fun(fun, closure) = &callee else {
+                    unreachable!()
+                };
+                let mut environment = Environment::new(Some(closure.clone()));
+                // the parameters take the first slots
+                for (param, argument) in fun.params.iter().zip(arguments) {
+                    environment.define(param.symbol(), argument);
                 }
-
-                let super_outer = crate::ENVIRONMENT.borrow().clone();
-                // bind this to the environment
-                if this.is_some() {
-                    crate::ENVIRONMENT =
-                        Lazy::new(|| Rc::new(RefCell::new(Environment::new(None))));
-                    crate::ENVIRONMENT
-                        .borrow_mut()
-                        .set_enclosing(Some(Rc::new(RefCell::new(super_outer.clone()))));
-                    crate::ENVIRONMENT.borrow_mut().define(
-                        "this".to_string(),
-                        Box::new(Value::Instance(this.clone().unwrap())),
-                    );
+                if fun.is_generator {
+                    return Ok(Value::Generator(Generator::new(GeneratorState::Tree {
+                        function: fun.clone(),
+                        environment: Rc::new(RefCell::new(environment)),
+                        resume: Vec::new(),
+                    })));
                 }
-
-                let outer = Rc::new(RefCell::new(crate::ENVIRONMENT.borrow().clone()));
-                crate::ENVIRONMENT = Lazy::new(|| Rc::new(RefCell::new(Environment::new(None))));
-                crate::ENVIRONMENT
-                    .borrow_mut()
-                    .set_enclosing(Some(outer.clone()));
-
-                // pass the arguments to the function
-                for (i, param) in fun.params.iter().enumerate() {
-                    if let TokenType::Identifier(param_name) = param.token_type.clone() {
-                        crate::ENVIRONMENT
-                            .borrow_mut()
-                            .define(param_name, arguments[i].clone());
-                    }
-                }
-
-                let mut ret_val = {
-                    // execute the function body
-                    if let Err(e) = fun.body.excute() {
-                        if e.message == "return".to_string() {
-                            crate::ENVIRONMENT.borrow().get(Token {
-                                token_type: TokenType::Identifier("return".to_string()),
-                                lexeme: "return".to_string(),
-                                line: 0,
-                            })
-                        } else {
-                            Err(e)
+                crate::interrupt::check()?;
+                crate::limits::step()?;
+                crate::limits::enter_call(&fun.name)?;
+                crate::profiler::enter(fun.profile_id());
+                let result = fun.body.execute_in(Rc::new(RefCell::new(environment)));
+                crate::profiler::exit();
+                crate::limits::exit_call();
+                let ret_val = match result {
+                    Ok(()) => Value::Nil,
+                    Err(e) if e.message == "return" => {
+                        if let Some((next, next_arguments)) = unsafe { TAIL_CALL.take() } {
+                            callee = next;
+                            arguments = next_arguments;
+                            continue;
                         }
-                    } else {
-                        Ok(Box::new(Value::Nil))
+                        unsafe { RETURN_VALUE.take() }.unwrap_or(Value::Nil)
                     }
+                    Err(e) => return Err(e),
+                };
+                return if fun.is_initializer {
+                    // bound methods close over the environment holding `this`
+                    closure.borrow().get_at(
+                        0,
+                        0,
+                        &Token {
+                            token_type: TokenType::This,
+                            lexeme: "this".to_string(),
+                            line: 0,
+                            column: 0,
+                        },
+                    )
+                } else {
+                    Ok(ret_val)
                 };
-                if fun.is_initializer {
-                    ret_val = crate::ENVIRONMENT.borrow().get(Token {
-                        token_type: TokenType::Identifier("this".to_string()),
-                        lexeme: "this".to_string(),
-                        line: 0,
-                    });
-                }
-                crate::ENVIRONMENT.borrow_mut().from(previous);
-                ret_val
             }
         } else if let Value::Builtin(builtin) = self {
             builtin.call(arguments)
-        } else if let Value::Class {
-            class,
-            methods,
-            super_class,
-        } = self
-        {
-            let mut instance = Instance {
+        } else if let Value::BuiltinMethod(builtin, this) = self {
+            builtin.call(std::iter::once((**this).clone()).chain(arguments).collect())
+        } else if let Value::Closure(closure, _) = self {
+            // each call from native code runs on a new machine, so its frames don't count
+            // towards the depth of the one that called in
+            crate::limits::enter_call(&Token {
+                token_type: TokenType::Identifier(Symbol::intern(&closure.function.name)),
+                lexeme: closure.function.name.clone(),
+                line: 0,
+                column: 0,
+            })?;
+            let result = crate::vm::Vm::new().call(self.clone(), arguments);
+            crate::limits::exit_call();
+            result
+        } else if let Value::Class(class) = self {
+            let instance = crate::gc::track(Rc::new(RefCell::new(Instance {
                 class: class.clone(),
                 fields: HashMap::new(),
-                methods: methods.clone(),
-                super_class: super_class.clone(),
-            };
-            let initializer = instance.methods.get_mut("init").cloned();
-            let instance = Rc::new(RefCell::new(instance));
-            if let Some(mut initializer) = initializer {
+            })));
+            if let Some(mut initializer) = class.methods.get(&Symbol::INIT).cloned() {
                 initializer.bind(instance.clone());
                 initializer.call(arguments)
             } else {
-                Ok(Box::new(Value::Instance(instance)))
+                Ok(Value::Instance(instance))
             }
+        } else if let Some(method) = self.call_method() {
+            method.call(arguments)
         } else {
             Err(Error {
                 line: 0,
+                column: 0,
                 loc: "NoFun".to_string(),
                 message: "Value that is not funciton can't be called".to_string(),
             })
         }
     }
 
+    fn is_variadic(&self) -> bool {
+        if let Value::Builtin(builtin) = self {
+            builtin.variadic
+        } else if let Value::BuiltinMethod(builtin, _) = self {
+            builtin.variadic
+        } else {
+            false
+        }
+    }
+
     fn is_callable(&self) -> bool {
-        if let Value::Fun(_, _, _) = self {
+        if let Value::Fun(_, _) = self {
+            true
+        } else if let Value::Builtin(_) | Value::BuiltinMethod(_, _) = self {
             true
-        } else if let Value::Builtin(_) = self {
+        } else if let Value::Closure(_, _) = self {
             true
-        } else if let Value::Class {
-            class: _,
-            methods: _,
-            super_class: _,
-        } = self
-        {
+        } else if let Value::Class(_) = self {
             true
         } else {
-            false
+            self.call_method().is_some()
         }
     }
 }
 
 impl std::ops::Add for Value {
-    type Output = Result<Box<Value>, Error>;
+    type Output = Result<Value, Error>;
 
     fn add(self, other: Self) -> Self::Output {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Box::new(Value::Number(a + b))),
-            (Value::String(a), Value::String(b)) => Ok(Box::new(Value::String(a + &b))),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+            (Value::String(a), Value::String(b)) => Ok(Value::String(format!("{}{}", a, b).into())),
             (Value::Array(a), Value::Array(b)) => {
-                let mut array = a.clone();
-                array.extend(b);
-                Ok(Box::new(Value::Array(array)))
+                let mut array = a.borrow().clone();
+                array.extend(b.borrow().iter().cloned());
+                Ok(Value::array(array))
             }
             _ => Err(Error::new(0, "".to_string(), "".to_string())),
         }
@@ -244,61 +597,63 @@ impl std::ops::Add for Value {
 }
 
 impl std::ops::Sub for Value {
-    type Output = Result<Box<Value>, Error>;
+    type Output = Result<Value, Error>;
 
     fn sub(self, other: Self) -> Self::Output {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Box::new(Value::Number(a - b))),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a - b)),
             _ => Err(Error::new(0, "".to_string(), "".to_string())),
         }
     }
 }
 
 impl std::ops::Mul for Value {
-    type Output = Result<Box<Value>, Error>;
+    type Output = Result<Value, Error>;
 
     fn mul(self, other: Self) -> Self::Output {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Box::new(Value::Number(a * b))),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
             _ => Err(Error::new(0, "".to_string(), "".to_string())),
         }
     }
 }
 
 impl std::ops::Div for Value {
-    type Output = Result<Box<Value>, Error>;
+    type Output = Result<Value, Error>;
 
     fn div(self, other: Self) -> Self::Output {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Box::new(Value::Number(a / b))),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a / b)),
             _ => Err(Error::new(0, "".to_string(), "".to_string())),
         }
     }
 }
 
 impl std::ops::Neg for Value {
-    type Output = Result<Box<Value>, Error>;
+    type Output = Result<Value, Error>;
 
     fn neg(self) -> Self::Output {
         match self {
-            Value::Number(n) => Ok(Box::new(Value::Number(-n))),
+            Value::Number(n) => Ok(Value::Number(-n)),
             _ => Err(Error::new(0, "".to_string(), "".to_string())),
         }
     }
 }
 
 impl std::ops::Not for Value {
-    type Output = Result<Box<Value>, Error>;
+    type Output = Result<Value, Error>;
 
     fn not(self) -> Self::Output {
         match self {
-            Value::Boolean(b) => Ok(Box::new(Value::Boolean(!b))),
-            Value::Nil => Ok(Box::new(Value::Boolean(true))),
+            Value::Boolean(b) => Ok(Value::Boolean(!b)),
+            Value::Nil => Ok(Value::Boolean(true)),
             _ => Err(Error::new(0, "".to_string(), "".to_string())),
         }
     }
 }
 
+/// Arrays are equal when their elements are, other heap values only to themselves. A bound
+/// method is equal to the same method bound to the same instance.
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -306,6 +661,36 @@ impl PartialEq for Value {
             (Value::String(a), Value::String(b)) => a == b,
             (Value::Boolean(a), Value::Boolean(b)) => a == b,
             (Value::Nil, Value::Nil) => true,
+            (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b) || *a.borrow() == *b.borrow(),
+            (
+                Value::ArrayObject { array, index },
+                Value::ArrayObject {
+                    array: other,
+                    index: other_index,
+                },
+            ) => Rc::ptr_eq(array, other) && index == other_index,
+            (Value::Range(a), Value::Range(b)) => a == b,
+            (Value::Generator(a), Value::Generator(b)) => Rc::ptr_eq(a, b),
+            (Value::Namespace(a), Value::Namespace(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Builtin(a), Value::Builtin(b)) => Rc::ptr_eq(a, b),
+            (Value::BuiltinMethod(a, this), Value::BuiltinMethod(b, other)) => {
+                std::ptr::eq(*a, *b) && this == other
+            }
+            (Value::Fun(a, closure), Value::Fun(b, other)) => {
+                Rc::ptr_eq(a, b)
+                    && (Rc::ptr_eq(closure, other)
+                        || closure.borrow().binds_same_this(&other.borrow()))
+            }
+            (Value::Closure(a, this), Value::Closure(b, other)) => {
+                Rc::ptr_eq(a, b)
+                    && match (this, other) {
+                        (Some(this), Some(other)) => Rc::ptr_eq(this, other),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
             _ => false,
         }
     }
@@ -320,6 +705,67 @@ impl Value {
     }
 }
 
+/// A value usable as a map key, with `Hash` and `Eq` consistent with `PartialEq`. `NaN` isn't
+/// equal to itself and an array can change while it is a key, so both are rejected. An
+/// instance is a key by identity, its `__eq__` isn't called.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HashKey(Value);
+
+impl HashKey {
+    pub fn new(value: Value) -> Result<Self, Error> {
+        let error = |message: &str| Error::new(0, "".to_string(), message.to_string());
+        match &value {
+            Value::Number(n) if n.is_nan() => Err(error("NaN can't be a key")),
+            Value::Range(range)
+                if [range.start, range.end, range.step]
+                    .iter()
+                    .any(|n| n.is_nan()) =>
+            {
+                Err(error("NaN can't be a key"))
+            }
+            Value::Array(_) | Value::ArrayObject { .. } => Err(error("An array can't be a key")),
+            Value::BuiltinMethod(_, this) => HashKey::new(*this.clone()).map(|_| HashKey(value)),
+            _ => Ok(HashKey(value)),
+        }
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl Eq for HashKey {}
+
+impl std::hash::Hash for HashKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        // `0.0 == -0.0`
+        fn number<H: std::hash::Hasher>(n: f64, state: &mut H) {
+            (if n == 0.0 { 0.0 } else { n }).to_bits().hash(state);
+        }
+        std::mem::discriminant(&self.0).hash(state);
+        match &self.0 {
+            Value::Number(n) => number(*n, state),
+            Value::String(s) => s.hash(state),
+            Value::Boolean(b) => b.hash(state),
+            Value::Range(range) => {
+                for n in [range.start, range.end, range.step] {
+                    number(n, state);
+                }
+            }
+            // a bound method is equal to the same method bound to the same instance anew
+            Value::Fun(fun, _) => Rc::as_ptr(fun).hash(state),
+            Value::Closure(closure, _) => Rc::as_ptr(closure).hash(state),
+            Value::BuiltinMethod(builtin, _) => std::ptr::from_ref(*builtin).hash(state),
+            Value::Builtin(builtin) => Rc::as_ptr(builtin).hash(state),
+            Value::Class(class) => Rc::as_ptr(class).hash(state),
+            Value::Instance(instance) => Rc::as_ptr(instance).hash(state),
+            Value::Generator(generator) => Rc::as_ptr(generator).hash(state),
+            Value::Namespace(namespace) => Rc::as_ptr(namespace).hash(state),
+            Value::Nil | Value::Array(_) | Value::ArrayObject { .. } => {}
+        }
+    }
+}
+
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -327,16 +773,50 @@ impl std::fmt::Display for Value {
             Value::String(s) => write!(f, "{}", s),
             Value::Boolean(b) => write!(f, "{}", b),
             Value::Nil => write!(f, "Nil"),
-            Value::Fun(fun, _, _) => write!(f, "{}", fun),
-            Value::Builtin(_) => write!(f, "<builtin fn>"),
-            Value::Class {
-                class,
-                methods: _,
-                super_class: _,
-            } => write!(f, "{}", class),
+            Value::Fun(fun, _) => write!(f, "{}", fun),
+            Value::Builtin(_) | Value::BuiltinMethod(_, _) => write!(f, "<builtin fn>"),
+            Value::Closure(closure, _) => write!(f, "{}", closure.function),
+            Value::Class(class) => write!(f, "{}", class.name),
             Value::Instance(instance) => write!(f, "{}", instance.borrow()),
-            Value::Array(a) => write!(f, "{:?}", a),
-            Value::ArrayObject { array, index } => write!(f, "{:?}[{}]", array, index),
+            Value::Array(a) => write!(f, "{:?}", a.borrow()),
+            Value::ArrayObject { array, index } => write!(f, "{:?}[{}]", array.borrow(), index),
+            Value::Range(range) => {
+                write!(f, "range({}, {}, {})", range.start, range.end, range.step)
+            }
+            Value::Generator(_) => write!(f, "<generator>"),
+            Value::Namespace(namespace) => write!(f, "<module {}>", namespace.name),
+        }
+    }
+}
+
+/// Written by hand to keep the output of arrays free of the `RefCell` wrapper
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "Number({:?})", n),
+            Value::String(s) => write!(f, "String({:?})", s),
+            Value::Boolean(b) => write!(f, "Boolean({:?})", b),
+            Value::Array(a) => write!(f, "Array({:?})", a.borrow()),
+            Value::Nil => write!(f, "Nil"),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl Trace for RefCell<Vec<Value>> {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        let Ok(array) = self.try_borrow() else {
+            return false;
+        };
+        for value in array.iter() {
+            trace_value(value, visit);
+        }
+        true
+    }
+
+    fn clear(&self) {
+        if let Ok(mut array) = self.try_borrow_mut() {
+            array.clear();
         }
     }
 }
@@ -1,29 +1,42 @@
 use crate::ast::stmt::function::Builtin;
+use crate::ast::stmt::{Instance, LoxClass};
+use crate::ast::value::Range;
 use crate::ast::Value;
+use crate::error::Error;
+use crate::interner::Symbol;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 pub static CLOCK: Builtin = Builtin {
     arity: 0,
-    call: |_: Vec<Box<Value>>| {
+    variadic: false,
+    call: |_: Vec<Value>| {
         let time = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs_f64();
-        Ok(Box::new(Value::Number(time)))
+        Ok(Value::Number(time))
     },
 };
 
 pub static STR: Builtin = Builtin {
     arity: 1,
-    call: |args: Vec<Box<Value>>| Ok(Box::new(Value::String(args[0].to_string()))),
+    variadic: false,
+    call: |args: Vec<Value>| Ok(Value::String(args[0].stringify()?.into())),
 };
 
 pub static LEN: Builtin = Builtin {
     arity: 1,
+    variadic: false,
     call: |args| {
-        if let Value::Array(array) = &*args[0] {
-            Ok(Box::new(Value::Number(array.len() as f64)))
-        } else if let Value::String(array) = &*args[0] {
-            Ok(Box::new(Value::Number(array.len() as f64)))
+        if let Value::Array(array) = &args[0] {
+            Ok(Value::Number(array.borrow().len() as f64))
+        } else if let Value::String(s) = &args[0] {
+            // strings are measured in characters, the same unit indexing uses
+            Ok(Value::Number(s.chars().count() as f64))
+        } else if let Value::Range(range) = &args[0] {
+            Ok(Value::Number(range.len() as f64))
         } else {
             Err(crate::error::Error::new(
                 0,
@@ -36,18 +49,19 @@ pub static LEN: Builtin = Builtin {
 
 pub static NUM: Builtin = Builtin {
     arity: 1,
+    variadic: false,
     call: |args| {
-        if let Value::String(s) = &*args[0] {
+        if let Value::String(s) = &args[0] {
             match s.parse::<f64>() {
-                Ok(n) => Ok(Box::new(Value::Number(n))),
+                Ok(n) => Ok(Value::Number(n)),
                 Err(_) => Err(crate::error::Error::new(
                     0,
                     "".to_string(),
                     "Argument must be a number".to_string(),
                 )),
             }
-        } else if let Value::Number(n) = &*args[0] {
-            Ok(Box::new(Value::Number(*n)))
+        } else if let Value::Number(n) = &args[0] {
+            Ok(Value::Number(*n))
         } else {
             Err(crate::error::Error::new(
                 0,
@@ -58,11 +72,479 @@ pub static NUM: Builtin = Builtin {
     },
 };
 
+pub static SLICE: Builtin = Builtin {
+    arity: 3,
+    variadic: false,
+    call: |args| {
+        let bound = |value: &Value| {
+            if let Value::Number(n) = value {
+                if n.fract() == 0f64 && *n >= 0f64 && n.is_finite() {
+                    return Ok(*n as usize);
+                }
+            }
+            Err(Error::new(
+                0,
+                "slice".to_string(),
+                "Bounds must be non-negative integers".to_string(),
+            ))
+        };
+        let (start, end) = (bound(&args[1])?, bound(&args[2])?);
+        let out_of_bounds = |len: usize| {
+            if start > end || end > len {
+                Err(Error::new(
+                    0,
+                    "slice".to_string(),
+                    "Slice out of bounds".to_string(),
+                ))
+            } else {
+                Ok(())
+            }
+        };
+        match &args[0] {
+            Value::Array(array) => {
+                let array = array.borrow();
+                out_of_bounds(array.len())?;
+                Ok(Value::array(array[start..end].to_vec()))
+            }
+            Value::String(s) => {
+                out_of_bounds(s.chars().count())?;
+                Ok(Value::String(
+                    s.chars()
+                        .skip(start)
+                        .take(end - start)
+                        .collect::<String>()
+                        .into(),
+                ))
+            }
+            _ => Err(Error::new(
+                0,
+                "slice".to_string(),
+                "Argument must be an array or a string".to_string(),
+            )),
+        }
+    },
+};
+
+/// `range(end)`, `range(start, end)` or `range(start, end, step)`, the numbers are made as
+/// they are iterated
+pub static RANGE: Builtin = Builtin {
+    arity: 1,
+    variadic: true,
+    call: |args| {
+        let error = |message: &str| Error::new(0, "range".to_string(), message.to_string());
+        let mut bounds = Vec::new();
+        for arg in &args {
+            match arg {
+                Value::Number(n) if n.is_finite() => bounds.push(*n),
+                _ => return Err(error("Arguments must be finite numbers")),
+            }
+        }
+        let (start, end, step) = match bounds[..] {
+            [end] => (0.0, end, 1.0),
+            [start, end] => (start, end, 1.0),
+            [start, end, step] => (start, end, step),
+            _ => return Err(error("Expected at most 3 arguments")),
+        };
+        if step == 0.0 {
+            return Err(error("Step must not be zero"));
+        }
+        Ok(Value::Range(Rc::new(Range { start, end, step })))
+    },
+};
+
 pub static INPUT: Builtin = Builtin {
     arity: 0,
-    call: |_: Vec<Box<Value>>| {
+    variadic: false,
+    call: |_: Vec<Value>| {
         let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
-        Ok(Box::new(Value::String(input.trim().to_string())))
+        std::io::stdin()
+            .read_line(&mut input)
+            .map_err(|e| io_error("input", e))?;
+        Ok(Value::String(input.trim().into()))
+    },
+};
+
+pub static READ_FILE: Builtin = Builtin {
+    arity: 1,
+    variadic: false,
+    call: |args| {
+        let path = string_arg("read_file", &args[0])?;
+        let contents = std::fs::read_to_string(path).map_err(|e| io_error("read_file", e))?;
+        Ok(Value::String(contents.into()))
     },
 };
+
+pub static WRITE_FILE: Builtin = Builtin {
+    arity: 2,
+    variadic: false,
+    call: |args| {
+        let path = string_arg("write_file", &args[0])?;
+        std::fs::write(path, args[1].to_string()).map_err(|e| io_error("write_file", e))?;
+        Ok(Value::Nil)
+    },
+};
+
+pub static APPEND_FILE: Builtin = Builtin {
+    arity: 2,
+    variadic: false,
+    call: |args| {
+        use std::io::Write;
+        let path = string_arg("append_file", &args[0])?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| io_error("append_file", e))?;
+        file.write_all(args[1].to_string().as_bytes())
+            .map_err(|e| io_error("append_file", e))?;
+        Ok(Value::Nil)
+    },
+};
+
+pub static READ_LINES: Builtin = Builtin {
+    arity: 1,
+    variadic: false,
+    call: |args| {
+        let path = string_arg("read_lines", &args[0])?;
+        let contents = std::fs::read_to_string(path).map_err(|e| io_error("read_lines", e))?;
+        let lines = contents
+            .lines()
+            .map(|line| Value::String(line.into()))
+            .collect();
+        Ok(Value::array(lines))
+    },
+};
+
+pub static FILE_EXISTS: Builtin = Builtin {
+    arity: 1,
+    variadic: false,
+    call: |args| {
+        let path = string_arg("file_exists", &args[0])?;
+        Ok(Value::Boolean(std::path::Path::new(path).exists()))
+    },
+};
+
+pub static LIST_DIR: Builtin = Builtin {
+    arity: 1,
+    variadic: false,
+    call: |args| {
+        let path = string_arg("list_dir", &args[0])?;
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(path).map_err(|e| io_error("list_dir", e))? {
+            let entry = entry.map_err(|e| io_error("list_dir", e))?;
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+        // read_dir gives no ordering guarantee, keep the output stable for scripts
+        names.sort();
+        Ok(Value::array(
+            names
+                .into_iter()
+                .map(|name| Value::String(name.into()))
+                .collect(),
+        ))
+    },
+};
+
+pub static REMOVE_FILE: Builtin = Builtin {
+    arity: 1,
+    variadic: false,
+    call: |args| {
+        let path = string_arg("remove_file", &args[0])?;
+        std::fs::remove_file(path).map_err(|e| io_error("remove_file", e))?;
+        Ok(Value::Nil)
+    },
+};
+
+fn string_arg<'a>(name: &str, value: &'a Value) -> Result<&'a str, Error> {
+    if let Value::String(s) = value {
+        Ok(s)
+    } else {
+        Err(Error::new(
+            0,
+            name.to_string(),
+            "Argument must be a string".to_string(),
+        ))
+    }
+}
+
+fn io_error(name: &str, e: std::io::Error) -> Error {
+    Error::new(0, name.to_string(), e.to_string())
+}
+
+pub static WRITE: Builtin = Builtin {
+    arity: 0,
+    variadic: true,
+    call: |args| {
+        use std::io::Write;
+        let mut handle_out = std::io::stdout();
+        write!(handle_out, "{}", join_values(&args)?).map_err(|e| io_error("write", e))?;
+        handle_out.flush().map_err(|e| io_error("write", e))?;
+        Ok(Value::Nil)
+    },
+};
+
+pub static FORMAT: Builtin = Builtin {
+    arity: 1,
+    variadic: true,
+    call: |args| {
+        let template = string_arg("format", &args[0])?;
+        Ok(Value::String(format_values(template, &args[1..])?.into()))
+    },
+};
+
+/// Collect the unreachable cycles now, and return the number of objects freed
+pub static GC: Builtin = Builtin {
+    arity: 0,
+    variadic: false,
+    call: |_: Vec<Value>| Ok(Value::Number(crate::gc::collect() as f64)),
+};
+
+/// The heap size in bytes, nil without the counting allocator, and the counts of the cycle
+/// collector
+pub static MEMORY: Builtin = Builtin {
+    arity: 0,
+    variadic: false,
+    call: |_: Vec<Value>| {
+        let stats = crate::gc::stats();
+        let mut fields = HashMap::new();
+        for (name, value) in [
+            ("heap", crate::allocator::allocated()),
+            ("objects", Some(stats.objects)),
+            ("collections", Some(stats.collections)),
+            ("freed", Some(stats.freed)),
+        ] {
+            let value = value.map_or(Value::Nil, |value| Value::Number(value as f64));
+            fields.insert(Symbol::intern(name), value);
+        }
+        let class = Rc::new(LoxClass {
+            name: "Memory".to_string(),
+            methods: HashMap::new(),
+            statics: RefCell::new(HashMap::new()),
+            super_class: None,
+        });
+        Ok(Value::Instance(crate::gc::track(Rc::new(RefCell::new(
+            Instance { class, fields },
+        )))))
+    },
+};
+
+/// The name of the type of a value
+pub static TYPE: Builtin = Builtin {
+    arity: 1,
+    variadic: false,
+    call: |args| Ok(Value::String(args[0].type_of().into())),
+};
+
+fn instance_arg(name: &str, value: &Value) -> Result<Rc<RefCell<Instance>>, Error> {
+    if let Value::Instance(instance) = value {
+        Ok(instance.clone())
+    } else {
+        Err(Error::new(
+            0,
+            name.to_string(),
+            "Argument must be an instance".to_string(),
+        ))
+    }
+}
+
+pub static CLASS_OF: Builtin = Builtin {
+    arity: 1,
+    variadic: false,
+    call: |args| {
+        let instance = instance_arg("class_of", &args[0])?;
+        let class = instance.borrow().class.clone();
+        Ok(Value::Class(class))
+    },
+};
+
+/// The names of the fields of an instance, or of the members of a module, sorted
+pub static FIELDS: Builtin = Builtin {
+    arity: 1,
+    variadic: false,
+    call: |args| {
+        let names = match &args[0] {
+            Value::Namespace(namespace) => namespace.names(),
+            value => instance_arg("fields", value)?
+                .borrow()
+                .fields
+                .keys()
+                .copied()
+                .collect(),
+        };
+        let mut names = names
+            .into_iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(Value::array(
+            names
+                .into_iter()
+                .map(|name| Value::String(name.into()))
+                .collect(),
+        ))
+    },
+};
+
+pub static HAS_FIELD: Builtin = Builtin {
+    arity: 2,
+    variadic: false,
+    call: |args| {
+        let name = Symbol::intern(string_arg("has_field", &args[1])?);
+        let has_field = match &args[0] {
+            Value::Namespace(namespace) => namespace.get(name).is_some(),
+            value => instance_arg("has_field", value)?
+                .borrow()
+                .fields
+                .contains_key(&name),
+        };
+        Ok(Value::Boolean(has_field))
+    },
+};
+
+pub static GET_FIELD: Builtin = Builtin {
+    arity: 2,
+    variadic: false,
+    call: |args| {
+        let name = string_arg("get_field", &args[1])?;
+        let value = match &args[0] {
+            Value::Namespace(namespace) => namespace.get(Symbol::intern(name)),
+            value => instance_arg("get_field", value)?
+                .borrow()
+                .fields
+                .get(&Symbol::intern(name))
+                .cloned(),
+        };
+        value.ok_or_else(|| Error::new(0, format!("'{}'", name), "Undefined property".to_string()))
+    },
+};
+
+/// Set the field and return its value
+pub static SET_FIELD: Builtin = Builtin {
+    arity: 3,
+    variadic: false,
+    call: |args| {
+        let instance = instance_arg("set_field", &args[0])?;
+        let name = Symbol::intern(string_arg("set_field", &args[1])?);
+        instance.borrow_mut().set(name, args[2].clone());
+        Ok(args[2].clone())
+    },
+};
+
+/// `generator.next()`, the value of the next `yield`, nil once the generator has returned
+pub static GENERATOR_NEXT: Builtin = Builtin {
+    arity: 0,
+    variadic: false,
+    call: |args| match &args[0] {
+        Value::Generator(generator) => Ok(generator.resume()?.unwrap_or(Value::Nil)),
+        _ => unreachable!("next of a value that isn't a generator"),
+    },
+};
+
+/// Join the values with spaces, the way `print a, b, c;` shows them
+pub fn join_values(values: &[Value]) -> Result<String, Error> {
+    Ok(values
+        .iter()
+        .map(|value| value.stringify())
+        .collect::<Result<Vec<String>, Error>>()?
+        .join(" "))
+}
+
+/// Fill the placeholders of `template` with `args`.
+///
+/// A placeholder looks like `{[index][:[align][width][.precision]]}`, where align is one of
+/// `<`, `>` or `^`. Placeholders without index take the arguments in order, `{{` and `}}`
+/// are literal braces.
+pub fn format_values(template: &str, args: &[Value]) -> Result<String, Error> {
+    let mut result = String::new();
+    let mut chars = template.chars().peekable();
+    let mut next_arg = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err(format_error("Unclosed '{' in format string")),
+                    }
+                }
+                let (index, options) = match spec.split_once(':') {
+                    Some((index, options)) => (index, options),
+                    None => (spec.as_str(), ""),
+                };
+                let index = if index.is_empty() {
+                    next_arg += 1;
+                    next_arg - 1
+                } else {
+                    index
+                        .parse::<usize>()
+                        .map_err(|_| format_error("Placeholder index must be an integer"))?
+                };
+                let value = args
+                    .get(index)
+                    .ok_or(format_error("Not enough arguments for format string"))?;
+                result.push_str(&format_value(value, options)?);
+            }
+            '}' => return Err(format_error("Unmatched '}' in format string")),
+            c => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+fn format_value(value: &Value, options: &str) -> Result<String, Error> {
+    let mut options = options.chars().peekable();
+    let align = match options.peek() {
+        Some('<') | Some('>') | Some('^') => options.next(),
+        _ => None,
+    };
+    let options = options.collect::<String>();
+    let (width, precision) = match options.split_once('.') {
+        Some((width, precision)) => (width, Some(precision)),
+        None => (options.as_str(), None),
+    };
+    let width = if width.is_empty() {
+        0
+    } else {
+        width
+            .parse::<usize>()
+            .map_err(|_| format_error("Width must be an integer"))?
+    };
+    let precision = match precision {
+        Some(precision) => Some(
+            precision
+                .parse::<usize>()
+                .map_err(|_| format_error("Precision must be an integer"))?,
+        ),
+        None => None,
+    };
+    let text = match (value, precision) {
+        (Value::Number(n), Some(precision)) => format!("{:.*}", precision, n),
+        (_, Some(precision)) => value.stringify()?.chars().take(precision).collect(),
+        (_, None) => value.stringify()?,
+    };
+    // numbers are right aligned by default, everything else is left aligned
+    let align = align.unwrap_or(if let Value::Number(_) = value {
+        '>'
+    } else {
+        '<'
+    });
+    Ok(match align {
+        '>' => format!("{:>1$}", text, width),
+        '^' => format!("{:^1$}", text, width),
+        _ => format!("{:<1$}", text, width),
+    })
+}
+
+fn format_error(message: &str) -> Error {
+    Error::new(0, "format".to_string(), message.to_string())
+}
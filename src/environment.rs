@@ -1,262 +1,231 @@
+use crate::gc::{trace_value, Trace};
+use crate::interner::Symbol;
 use crate::{Error, Token, Value};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Environment {
     enclosing: Option<Rc<RefCell<Environment>>>,
-    values: HashMap<String, Box<Value>>,
+    /// Local variables, in the slots assigned by the resolver
+    slots: Vec<Value>,
+    /// Global variables, only the outermost environment has them
+    values: HashMap<Symbol, Value>,
+    /// The globals that can't be assigned
+    constants: HashSet<Symbol>,
+    /// Whether the cycle collector knows about it
+    tracked: bool,
 }
 
 impl Environment {
     pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
         Self {
             enclosing,
+            slots: Vec::new(),
             values: HashMap::new(),
+            constants: HashSet::new(),
+            tracked: false,
         }
     }
 
-    pub fn set_enclosing(&mut self, enclosing: Option<Rc<RefCell<Environment>>>) {
-        self.enclosing = enclosing;
+    /// Track the environment and the ones enclosing it with the cycle collector, when a
+    /// closure refers to it and it can become part of a cycle
+    pub fn capture(environment: &Rc<RefCell<Self>>) {
+        let mut current = Some(environment.clone());
+        while let Some(environment) = current {
+            if environment.borrow().tracked {
+                break;
+            }
+            environment.borrow_mut().tracked = true;
+            current = environment.borrow().enclosing.clone();
+            crate::gc::track(environment);
+        }
     }
 
     pub fn get_enclosing(&self) -> Option<Rc<RefCell<Environment>>> {
         self.enclosing.clone()
     }
 
-    pub fn from(&mut self, other: Rc<RefCell<Self>>) {
-        self.values = other.borrow().values.clone();
-        self.enclosing = other.borrow().enclosing.clone();
+    /// Define a global by name, or the next local slot of an inner environment
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        if self.enclosing.is_some() {
+            self.slots.push(value);
+        } else {
+            self.values.insert(name, value);
+        }
     }
 
-    pub fn define(&mut self, name: String, value: Box<Value>) {
-        self.values.insert(name, value);
+    /// Define a constant global by name, or the next local slot of an inner environment, the
+    /// resolver checks the local ones
+    pub fn define_constant(&mut self, name: Symbol, value: Value) {
+        if self.enclosing.is_none() {
+            self.constants.insert(name);
+        }
+        self.define(name, value);
     }
 
-    pub fn get(&self, token: Token) -> Result<Box<Value>, Error> {
-        match self.values.get(&token.lexeme) {
-            Some(value) => Ok(value.clone()),
-            None => {
-                if let Some(enclosing) = &self.enclosing {
-                    enclosing.borrow().get(token)
-                } else {
-                    Err(Error::new(
-                        token.line,
-                        token.lexeme,
-                        "Undefined variable".to_string(),
-                    ))
-                }
-            }
-        }
+    /// Remove a global, constant or not
+    pub fn undefine(&mut self, name: Symbol) -> Option<Value> {
+        self.constants.remove(&name);
+        self.values.remove(&name)
     }
 
-    pub fn get_at(&self, distance: usize, token: Token) -> Result<Box<Value>, Error> {
-        if let Some(value) = self.ancestor(distance).borrow().values.get(&token.lexeme) {
-            Ok(value.clone())
-        } else {
-            Err(Error::new(
-                token.line,
-                token.lexeme,
-                "Undefined variable".to_string(),
-            ))
+    /// The names of the constant globals
+    pub fn constants(&self) -> impl Iterator<Item = Symbol> + '_ {
+        self.constants.iter().copied()
+    }
+
+    /// Look a global up by name
+    pub fn get(&self, token: &Token) -> Result<Value, Error> {
+        self.get_global(token.symbol())
+            .ok_or_else(|| undefined(token))
+    }
+
+    /// Look a global up by symbol, without building an error
+    pub fn get_global(&self, name: Symbol) -> Option<Value> {
+        match self.values.get(&name) {
+            Some(value) => Some(value.clone()),
+            None => self.enclosing.as_ref()?.borrow().get_global(name),
         }
     }
 
-    pub fn assign(target: Rc<RefCell<Self>>, token: Token, value: Box<Value>) -> Result<(), Error> {
-        let mut target = target.borrow_mut();
-        let v = target.values.get(&token.lexeme);
-        match v {
-            Some(_) => {
-                target.values.insert(token.lexeme, value);
-                Ok(())
-            }
-            None => {
-                if let Some(enclosing) = target.enclosing.clone() {
-                    Self::assign(enclosing, token, value)
-                } else {
-                    Err(Error::new(
-                        token.line,
-                        token.lexeme,
-                        "Undefined variable".to_string(),
-                    ))
-                }
+    /// The globals defined in this environment
+    pub fn globals(&self) -> &HashMap<Symbol, Value> {
+        &self.values
+    }
+
+    pub fn get_at(&self, distance: usize, slot: usize, token: &Token) -> Result<Value, Error> {
+        if distance > 0 {
+            if let Some(enclosing) = &self.enclosing {
+                return enclosing.borrow().get_at(distance - 1, slot, token);
             }
+        } else if let Some(value) = self.slots.get(slot) {
+            return Ok(value.clone());
         }
+        Err(undefined(token))
     }
 
-    pub fn assign_array(
+    /// Assign a global by name
+    pub fn assign(target: Rc<RefCell<Self>>, token: &Token, value: Value) -> Result<(), Error> {
+        Self::assign_global(target, token.symbol(), value)
+            .map_err(|message| Error::new(token.line, token.lexeme.clone(), message.to_string()))
+    }
+
+    /// Fails with the message of the error if the global is not defined or is a constant
+    pub fn assign_global(
         target: Rc<RefCell<Self>>,
-        token: Token,
-        indeces: Vec<usize>,
-        value: Box<Value>,
-    ) -> Result<(), Error> {
-        let mut target = target.borrow_mut();
-        let v = target.values.get_mut(&token.lexeme);
-        match v {
-            Some(v) => {
-                if let Value::Array(array) = v.as_mut() {
-                    let mut current = array;
-                    for index in indeces.iter().take(indeces.len() - 1) {
-                        if let Value::Array(array) = current[*index].as_mut() {
-                            current = array;
-                        } else {
-                            return Err(Error::new(
-                                token.line,
-                                token.lexeme,
-                                "Not an array".to_string(),
-                            ));
-                        }
-                    }
-                    current[indeces[indeces.len() - 1]] = value;
-                    Ok(())
-                } else {
-                    Err(Error::new(
-                        token.line,
-                        token.lexeme,
-                        "Not an array".to_string(),
-                    ))
-                }
-            }
-            None => {
-                if let Some(enclosing) = target.enclosing.clone() {
-                    Self::assign_array(enclosing, token, indeces, value)
-                } else {
-                    Err(Error::new(
-                        token.line,
-                        token.lexeme,
-                        "Undefined variable".to_string(),
-                    ))
-                }
-            }
-        }
+        name: Symbol,
+        value: Value,
+    ) -> Result<(), &'static str> {
+        Self::replace_global(target, name, value).map(|_| ())
     }
 
-    pub fn assign_at(
+    /// Assign a global and return the value it had, to update it in place and put it back
+    pub fn replace_global(
         target: Rc<RefCell<Self>>,
-        distance: usize,
-        token: Token,
-        value: Box<Value>,
-    ) -> Result<(), Error> {
-        let ancestor_ = Self::ancestor_mut(target.clone(), distance);
-        let mut ancestor = ancestor_.borrow_mut();
-        if ancestor.values.get(&token.lexeme).is_some() {
-            ancestor.values.insert(token.lexeme, value);
-            Ok(())
+        name: Symbol,
+        value: Value,
+    ) -> Result<Value, &'static str> {
+        let mut target = target.borrow_mut();
+        if target.constants.contains(&name) {
+            Err("Can't assign to a constant")
+        } else if let Some(v) = target.values.get_mut(&name) {
+            Ok(std::mem::replace(v, value))
+        } else if let Some(enclosing) = target.enclosing.clone() {
+            Self::replace_global(enclosing, name, value)
         } else {
-            Err(Error::new(
-                token.line,
-                token.lexeme,
-                "Undefined variable".to_string(),
-            ))
+            Err("Undefined variable")
         }
     }
 
-    pub fn assign_array_at(
-        target: Rc<RefCell<Self>>,
+    pub fn assign_at(
+        &mut self,
         distance: usize,
-        token: Token,
-        indeces: Vec<usize>,
-        value: Box<Value>,
+        slot: usize,
+        token: &Token,
+        value: Value,
     ) -> Result<(), Error> {
-        let ancestor = Self::ancestor_mut(target.clone(), distance);
-        let mut target = ancestor.borrow_mut();
-        let v = target.values.get_mut(&token.lexeme);
-        match v {
-            Some(v) => {
-                if let Value::Array(array) = v.as_mut() {
-                    let mut current = array;
-                    for index in indeces.iter().take(indeces.len() - 1) {
-                        if let Value::Array(array) = current[*index].as_mut() {
-                            current = array;
-                        } else {
-                            return Err(Error::new(
-                                token.line,
-                                token.lexeme,
-                                "Not an array".to_string(),
-                            ));
-                        }
-                    }
-                    current[indeces[indeces.len() - 1]] = value;
-                    Ok(())
-                } else {
-                    Err(Error::new(
-                        token.line,
-                        token.lexeme,
-                        "Not an array".to_string(),
-                    ))
-                }
-            }
-            None => Err(Error::new(
-                token.line,
-                token.lexeme,
-                "Undefined variable".to_string(),
-            )),
-        }
+        self.replace_at(distance, slot, token, value).map(|_| ())
     }
 
-    pub fn ancestor_mut(target: Rc<RefCell<Self>>, distance: usize) -> Rc<RefCell<Self>> {
-        let mut env = target.clone();
-        for _ in 0..distance {
-            let e = env.clone().borrow().enclosing.clone();
-            if let Some(e) = e {
-                env = e.clone();
-            } else {
-                return Rc::new(RefCell::new(Environment::new(None)));
+    /// Assign a local and return the value it had, to update it in place and put it back
+    pub fn replace_at(
+        &mut self,
+        distance: usize,
+        slot: usize,
+        token: &Token,
+        value: Value,
+    ) -> Result<Value, Error> {
+        if distance > 0 {
+            if let Some(enclosing) = &self.enclosing {
+                return enclosing
+                    .borrow_mut()
+                    .replace_at(distance - 1, slot, token, value);
             }
+        } else if let Some(v) = self.slots.get_mut(slot) {
+            return Ok(std::mem::replace(v, value));
         }
-        env
-    }
-
-    pub fn ancestor(&self, distance: usize) -> Rc<RefCell<Self>> {
-        let mut env = Rc::new(RefCell::new(self.clone()));
-        for _ in 0..distance {
-            let e = env.borrow().enclosing.clone();
-            if let Some(e) = e {
-                env = e.clone();
-            } else {
-                return Rc::new(RefCell::new(Environment::new(None)));
+        Err(undefined(token))
+    }
+
+    /// Whether both hold only the same `this` over the same enclosing environment, as the
+    /// bound methods of one instance do
+    pub fn binds_same_this(&self, other: &Self) -> bool {
+        match (
+            &self.enclosing,
+            &other.enclosing,
+            self.slots.as_slice(),
+            other.slots.as_slice(),
+        ) {
+            (Some(a), Some(b), [Value::Instance(x)], [Value::Instance(y)]) => {
+                Rc::ptr_eq(a, b) && Rc::ptr_eq(x, y)
             }
+            _ => false,
         }
-        env
     }
 
-    pub fn get_global_mut(target: Rc<RefCell<Self>>) -> Rc<RefCell<Self>> {
-        let mut env = target.clone();
-        loop {
-            let tmp = env.borrow().enclosing.clone();
-            if let Some(tmp) = tmp {
-                env = tmp;
-            } else {
-                break;
-            }
+    pub fn look_up_variable(
+        &self,
+        name: &Token,
+        expr: *const dyn crate::ast::Expr,
+    ) -> Result<Value, Error> {
+        let local = unsafe { crate::LOCALS.get(&expr) };
+        if let Some((distance, slot)) = local {
+            self.get_at(*distance, *slot, name)
+        } else {
+            self.get(name)
         }
-        env
     }
+}
 
-    pub fn get_global(&self) -> Rc<RefCell<Self>> {
-        let mut result = Rc::new(RefCell::new(self.clone()));
-        loop {
-            let tmp = result.borrow().enclosing.clone();
-            if let Some(tmp) = tmp {
-                result = tmp;
-            } else {
-                break;
-            }
+impl Trace for RefCell<Environment> {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        let Ok(environment) = self.try_borrow() else {
+            return false;
+        };
+        if let Some(enclosing) = &environment.enclosing {
+            visit(Rc::as_ptr(enclosing) as *const ());
+        }
+        for value in environment.slots.iter().chain(environment.values.values()) {
+            trace_value(value, visit);
         }
-        result
+        true
     }
 
-    pub fn look_up_variable(
-        &self,
-        name: Token,
-        expr: *const dyn crate::ast::Expr,
-    ) -> Result<Box<Value>, Error> {
-        let distance = unsafe { crate::LOCALS.get(&expr) };
-        if let Some(distance) = distance {
-            self.get_at(*distance, name)
-        } else {
-            self.get_global().borrow().get(name)
+    fn clear(&self) {
+        if let Ok(mut environment) = self.try_borrow_mut() {
+            environment.enclosing = None;
+            environment.slots.clear();
+            environment.values.clear();
         }
     }
 }
+
+fn undefined(token: &Token) -> Error {
+    Error::new(
+        token.line,
+        token.lexeme.clone(),
+        "Undefined variable".to_string(),
+    )
+}
@@ -4,17 +4,27 @@ use crate::token_type::TokenType;
 
 pub struct Error {
     pub line: usize,
+    /// Column counted in characters, 0 if unknown
+    pub column: usize,
     pub loc: String,
     pub message: String,
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "[line {}] Error {}, message: {}",
-            self.line, self.loc, self.message
-        )
+        if self.column > 0 {
+            write!(
+                f,
+                "[line {}:{}] Error {}, message: {}",
+                self.line, self.column, self.loc, self.message
+            )
+        } else {
+            write!(
+                f,
+                "[line {}] Error {}, message: {}",
+                self.line, self.loc, self.message
+            )
+        }
     }
 }
 
@@ -26,13 +36,20 @@ impl std::fmt::Debug for Error {
 
 impl Error {
     pub fn new(line: usize, loc: String, message: String) -> Self {
-        Self { line, loc, message }
+        Self {
+            line,
+            column: 0,
+            loc,
+            message,
+        }
     }
     pub fn report(token: Token, massage: String) -> Self {
-        if token.token_type == TokenType::Eof {
+        let mut error = if token.token_type == TokenType::Eof {
             Self::new(token.line, "at end".to_string(), massage)
         } else {
             Self::new(token.line, format!("at '{}'", token.lexeme), massage)
-        }
+        };
+        error.column = token.column;
+        error
     }
 }
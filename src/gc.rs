@@ -0,0 +1,158 @@
+//! Cycle collector for the heap objects behind `Rc`
+//!
+//! Reference counting frees most objects, the collector finds the groups only kept alive by
+//! references among themselves. It counts the references each tracked object gets from other
+//! tracked objects: if its strong count is higher, something else (the stack, a global, a
+//! local of the interpreter) holds it and it is a root. Objects not reachable from the roots
+//! are cycles, and clearing their contents lets reference counting free them.
+use crate::Value;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// Collect after this many new objects, or twice as many as survived the last collection if more
+const INITIAL_THRESHOLD: usize = 10_000;
+
+/// A heap object that can hold references to other tracked objects
+pub trait Trace {
+    /// Visit the address of every object it refers to, return false if it is borrowed and
+    /// can't be read
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool;
+    /// Drop the references it holds, to break the cycle it is part of
+    fn clear(&self) {}
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// Objects tracked after the last collection, and allocated since
+    pub objects: usize,
+    pub collections: usize,
+    /// Objects freed by all the collections
+    pub freed: usize,
+}
+
+struct Heap {
+    objects: Vec<Weak<dyn Trace>>,
+    /// Collect when `objects` grows to this length
+    threshold: usize,
+    collecting: bool,
+    stats: Stats,
+}
+
+static mut HEAP: Heap = Heap {
+    objects: Vec::new(),
+    threshold: INITIAL_THRESHOLD,
+    collecting: false,
+    stats: Stats {
+        objects: 0,
+        collections: 0,
+        freed: 0,
+    },
+};
+
+/// Register a new object with the collector, and collect if enough were allocated
+pub fn track<T: Trace + 'static>(object: Rc<T>) -> Rc<T> {
+    let heap = unsafe { &mut *std::ptr::addr_of_mut!(HEAP) };
+    let weak: Weak<dyn Trace> = Rc::downgrade(&object) as Weak<dyn Trace>;
+    heap.objects.push(weak);
+    if heap.objects.len() >= heap.threshold && !heap.collecting {
+        collect();
+    }
+    object
+}
+
+/// Free the unreachable cycles, return the number of objects freed
+pub fn collect() -> usize {
+    let heap = unsafe { &mut *std::ptr::addr_of_mut!(HEAP) };
+    heap.collecting = true;
+    let objects = heap
+        .objects
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect::<Vec<_>>();
+    let index = objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| (Rc::as_ptr(object) as *const (), i))
+        .collect::<HashMap<_, _>>();
+
+    // references from tracked objects, an object that can't be read is a root
+    let mut internal = vec![0; objects.len()];
+    let mut readable = vec![true; objects.len()];
+    for (i, object) in objects.iter().enumerate() {
+        readable[i] = object.trace(&mut |child| {
+            if let Some(&j) = index.get(&child) {
+                internal[j] += 1;
+            }
+        });
+    }
+    let mut reachable = vec![false; objects.len()];
+    let mut pending = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        // `objects` holds one of the strong references
+        if !readable[i] || Rc::strong_count(object) - 1 > internal[i] {
+            reachable[i] = true;
+            pending.push(i);
+        }
+    }
+    while let Some(i) = pending.pop() {
+        objects[i].trace(&mut |child| {
+            if let Some(&j) = index.get(&child) {
+                if !reachable[j] {
+                    reachable[j] = true;
+                    pending.push(j);
+                }
+            }
+        });
+    }
+
+    let mut freed = 0;
+    for (object, reachable) in objects.iter().zip(&reachable) {
+        if !reachable {
+            object.clear();
+            freed += 1;
+        }
+    }
+    heap.objects = objects
+        .iter()
+        .zip(&reachable)
+        .filter(|(_, reachable)| **reachable)
+        .map(|(object, _)| Rc::downgrade(object))
+        .collect();
+    drop(objects);
+
+    heap.threshold = (heap.objects.len() * 2).max(INITIAL_THRESHOLD);
+    heap.stats.collections += 1;
+    heap.stats.freed += freed;
+    heap.collecting = false;
+    freed
+}
+
+pub fn stats() -> Stats {
+    let heap = unsafe { &*std::ptr::addr_of!(HEAP) };
+    Stats {
+        objects: heap.objects.len(),
+        ..heap.stats
+    }
+}
+
+/// Visit the object a value refers to, if any
+pub fn trace_value(value: &Value, visit: &mut dyn FnMut(*const ())) {
+    match value {
+        Value::Fun(_, environment) => visit(Rc::as_ptr(environment) as *const ()),
+        Value::Closure(closure, this) => {
+            visit(Rc::as_ptr(closure) as *const ());
+            if let Some(this) = this {
+                visit(Rc::as_ptr(this) as *const ());
+            }
+        }
+        Value::Class(class) => visit(Rc::as_ptr(class) as *const ()),
+        Value::Instance(instance) => visit(Rc::as_ptr(instance) as *const ()),
+        Value::Generator(generator) => visit(Rc::as_ptr(generator) as *const ()),
+        Value::BuiltinMethod(_, this) => trace_value(this, visit),
+        Value::Namespace(namespace) => visit(Rc::as_ptr(namespace) as *const ()),
+        Value::Array(array) | Value::ArrayObject { array, .. } => {
+            visit(Rc::as_ptr(array) as *const ())
+        }
+        _ => {}
+    }
+}
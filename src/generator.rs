@@ -0,0 +1,200 @@
+//! Generators, functions declared with `fun*` and methods declared with `*name` that suspend
+//! at each `yield`
+//!
+//! Calling one creates a generator, which runs the body up to the next `yield` each time an
+//! element is asked for, by a `for` loop or by its `next()` method. The tree engine continues a body from the path of statements it was
+//! suspended in, see `Stmt::generate`. The vm saves the frame of the body, see `Vm::resume`.
+use crate::ast::stmt::Function;
+use crate::gc::{trace_value, Trace};
+use crate::vm::{Closure, Upvalue};
+use crate::{Environment, Error, Value, RETURN_VALUE, YIELD_VALUE};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct Generator {
+    pub state: RefCell<GeneratorState>,
+}
+
+pub enum GeneratorState {
+    /// Suspended in the tree engine, `environment` holds the arguments the body starts with
+    Tree {
+        function: Rc<Function>,
+        environment: Rc<RefCell<Environment>>,
+        resume: Vec<Resume>,
+    },
+    /// Suspended in the vm, with the slots of its frame and the upvalues closed over them
+    Vm {
+        closure: Rc<Closure>,
+        ip: usize,
+        slots: Vec<Value>,
+        upvalues: Vec<(usize, Rc<RefCell<Upvalue>>)>,
+    },
+    Running,
+    Done,
+}
+
+/// The closure, instruction pointer, slots and closed upvalues of a suspended vm frame
+pub type SuspendedFrame = (
+    Rc<Closure>,
+    usize,
+    Vec<Value>,
+    Vec<(usize, Rc<RefCell<Upvalue>>)>,
+);
+
+/// Where a statement suspended by a `yield` continues, pushed by each statement the `yield`
+/// unwinds through, the innermost first
+#[derive(Debug)]
+pub enum Resume {
+    Block {
+        index: usize,
+        environment: Rc<RefCell<Environment>>,
+    },
+    If {
+        then_branch: bool,
+    },
+    While,
+    ForIn {
+        iterable: Value,
+        position: usize,
+    },
+    Yield,
+}
+
+/// Whether the statement stopped at a `yield`
+pub fn is_yield(result: &Result<(), Error>) -> bool {
+    matches!(result, Err(e) if e.message == "yield")
+}
+
+impl Generator {
+    pub fn new(state: GeneratorState) -> Rc<Generator> {
+        crate::gc::track(Rc::new(Generator {
+            state: RefCell::new(state),
+        }))
+    }
+
+    /// Run to the next `yield` and return its value, `None` once the body has returned
+    pub fn resume(self: &Rc<Self>) -> Result<Option<Value>, Error> {
+        match self.state.replace(GeneratorState::Running) {
+            GeneratorState::Tree {
+                function,
+                environment,
+                mut resume,
+            } => {
+                crate::interrupt::check()?;
+                crate::limits::step()?;
+                crate::limits::enter_call(&function.name)?;
+                crate::profiler::enter(function.profile_id());
+                let result = function
+                    .body
+                    .generate_in(&mut resume, || environment.clone());
+                crate::profiler::exit();
+                crate::limits::exit_call();
+                match result {
+                    Err(e) if e.message == "yield" => {
+                        self.state.replace(GeneratorState::Tree {
+                            function,
+                            environment,
+                            resume,
+                        });
+                        Ok(Some(
+                            unsafe { (*std::ptr::addr_of_mut!(YIELD_VALUE)).take() }
+                                .unwrap_or(Value::Nil),
+                        ))
+                    }
+                    Err(e) if e.message == "return" => {
+                        unsafe { (*std::ptr::addr_of_mut!(RETURN_VALUE)).take() };
+                        self.state.replace(GeneratorState::Done);
+                        Ok(None)
+                    }
+                    result => {
+                        self.state.replace(GeneratorState::Done);
+                        result.map(|_| None)
+                    }
+                }
+            }
+            state @ GeneratorState::Vm { .. } => {
+                self.state.replace(state);
+                crate::vm::Vm::new().resume(self)
+            }
+            GeneratorState::Running => Err(already_running()),
+            GeneratorState::Done => {
+                self.state.replace(GeneratorState::Done);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Take the state of a generator suspended in the vm to resume it, `None` if it is done
+    pub fn resume_vm(&self) -> Result<Option<SuspendedFrame>, Error> {
+        match self.state.replace(GeneratorState::Running) {
+            GeneratorState::Vm {
+                closure,
+                ip,
+                slots,
+                upvalues,
+            } => Ok(Some((closure, ip, slots, upvalues))),
+            GeneratorState::Done => {
+                self.state.replace(GeneratorState::Done);
+                Ok(None)
+            }
+            GeneratorState::Running => Err(already_running()),
+            GeneratorState::Tree { .. } => unreachable!("resume a tree generator in the vm"),
+        }
+    }
+}
+
+fn already_running() -> Error {
+    Error::new(
+        0,
+        "generator".to_string(),
+        "Generator is already running".to_string(),
+    )
+}
+
+impl Trace for Generator {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        let Ok(state) = self.state.try_borrow() else {
+            return false;
+        };
+        match &*state {
+            GeneratorState::Tree {
+                environment,
+                resume,
+                ..
+            } => {
+                visit(Rc::as_ptr(environment) as *const ());
+                for entry in resume {
+                    match entry {
+                        Resume::Block { environment, .. } => {
+                            visit(Rc::as_ptr(environment) as *const ())
+                        }
+                        Resume::ForIn { iterable, .. } => trace_value(iterable, visit),
+                        _ => {}
+                    }
+                }
+            }
+            GeneratorState::Vm {
+                closure,
+                slots,
+                upvalues,
+                ..
+            } => {
+                visit(Rc::as_ptr(closure) as *const ());
+                for value in slots {
+                    trace_value(value, visit);
+                }
+                for (_, upvalue) in upvalues {
+                    visit(Rc::as_ptr(upvalue) as *const ());
+                }
+            }
+            GeneratorState::Running | GeneratorState::Done => {}
+        }
+        true
+    }
+
+    fn clear(&self) {
+        if let Ok(mut state) = self.state.try_borrow_mut() {
+            *state = GeneratorState::Done;
+        }
+    }
+}
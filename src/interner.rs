@@ -0,0 +1,103 @@
+//! Interning of identifiers and string constants
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// An interned string, cheap to copy, compare and hash
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+/// Names known in advance get fixed symbols, in the order of `Symbol::PREDEFINED`
+impl Symbol {
+    pub const EMPTY: Symbol = Symbol(0);
+    pub const INIT: Symbol = Symbol(1);
+    pub const THIS: Symbol = Symbol(2);
+    pub const SUPER: Symbol = Symbol(3);
+    pub const ADD: Symbol = Symbol(4);
+    pub const SUB: Symbol = Symbol(5);
+    pub const MUL: Symbol = Symbol(6);
+    pub const DIV: Symbol = Symbol(7);
+    pub const EQ: Symbol = Symbol(8);
+    pub const LT: Symbol = Symbol(9);
+    pub const STR: Symbol = Symbol(10);
+    pub const INDEX: Symbol = Symbol(11);
+    pub const SETINDEX: Symbol = Symbol(12);
+    pub const CALL: Symbol = Symbol(13);
+    pub const ITER: Symbol = Symbol(14);
+    pub const NEXT: Symbol = Symbol(15);
+    const PREDEFINED: [&'static str; 16] = [
+        "",
+        "init",
+        "this",
+        "super",
+        "__add__",
+        "__sub__",
+        "__mul__",
+        "__div__",
+        "__eq__",
+        "__lt__",
+        "__str__",
+        "__index__",
+        "__setindex__",
+        "__call__",
+        "iter",
+        "next",
+    ];
+
+    pub fn intern(name: &str) -> Self {
+        unsafe { (*std::ptr::addr_of_mut!(INTERNER)).intern(name) }
+    }
+
+    /// The shared storage of the string
+    pub fn to_rc(self) -> Rc<str> {
+        unsafe { INTERNER.strings[self.0 as usize].clone() }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        // interned strings are never freed, and moving the `Rc` doesn't move the data
+        unsafe { &*Rc::as_ptr(&INTERNER.strings[self.0 as usize]) }
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+    strings: Vec<Rc<str>>,
+}
+
+impl Interner {
+    fn new() -> Self {
+        let mut interner = Self {
+            symbols: HashMap::new(),
+            strings: Vec::new(),
+        };
+        for name in Symbol::PREDEFINED {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.strings.len() as u32);
+        let name: Rc<str> = name.into();
+        self.strings.push(name.clone());
+        self.symbols.insert(name, symbol);
+        symbol
+    }
+}
+
+static mut INTERNER: Lazy<Interner> = Lazy::new(Interner::new);
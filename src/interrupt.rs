@@ -0,0 +1,74 @@
+//! Cooperative interruption of a running script
+//!
+//! The flag is checked at loop back-edges and function entry, so a run stops at the next
+//! iteration or call after it is set.
+use crate::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Cancels the current run from any thread
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptHandle {
+    flag: &'static AtomicBool,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn handle() -> InterruptHandle {
+    InterruptHandle { flag: &INTERRUPTED }
+}
+
+/// Forget an interrupt that arrived while nothing was running
+pub fn clear() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn check() -> Result<(), Error> {
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        INTERRUPTED.store(false, Ordering::Relaxed);
+        Err(Error::new(0, "".to_string(), "Interrupted".to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Make Ctrl-C interrupt the run instead of killing the process
+#[cfg(unix)]
+pub fn catch_ctrl_c() {
+    const SIGINT: i32 = 2;
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    extern "C" fn on_sigint(_: i32) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+    unsafe {
+        signal(SIGINT, on_sigint);
+    }
+}
+
+/// Make Ctrl-C interrupt the run instead of killing the process
+#[cfg(windows)]
+pub fn catch_ctrl_c() {
+    const CTRL_C_EVENT: u32 = 0;
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: extern "system" fn(u32) -> i32, add: i32) -> i32;
+    }
+    extern "system" fn on_ctrl(event: u32) -> i32 {
+        if event == CTRL_C_EVENT {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+            1
+        } else {
+            0
+        }
+    }
+    unsafe {
+        SetConsoleCtrlHandler(on_ctrl, 1);
+    }
+}
@@ -0,0 +1,385 @@
+//! A Lox interpreter, with a tree-walking engine and a bytecode vm
+//!
+//! The interpreter keeps its state in globals: runs share the global environment, and only
+//! one thread may run scripts at a time.
+#![deny(unused_must_use)]
+use ast::expr::Expr;
+use ast::stmt::function::Builtin;
+use clap::ValueEnum;
+use environment::Environment;
+use interner::Symbol;
+use once_cell::sync::Lazy;
+use scanner::Scanner;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::rc::Rc;
+use token::Token;
+use token_type::TokenType;
+
+mod allocator;
+mod ast;
+mod builtins;
+mod environment;
+mod error;
+mod gc;
+mod generator;
+mod interner;
+mod interrupt;
+mod limits;
+mod module;
+mod parser;
+mod profiler;
+mod scanner;
+mod token;
+mod token_type;
+mod vm;
+extern crate rlox_macro;
+
+pub use allocator::Counting;
+pub use ast::stmt::Stmt;
+pub use ast::value::{HashKey, Value};
+pub use builtins::*;
+pub use error::Error;
+pub use interrupt::InterruptHandle;
+pub use limits::{parse_size, run_on_interpreter_thread, Limits, DEFAULT_MAX_DEPTH};
+
+pub static mut ENVIRONMENT: Lazy<Rc<RefCell<Environment>>> =
+    Lazy::new(|| Rc::new(RefCell::new(Environment::new(None))));
+/// The scope distance and slot of every resolved local variable
+pub static mut LOCALS: Lazy<HashMap<*const dyn Expr, (usize, usize)>> =
+    Lazy::new(|| HashMap::new());
+/// The value of the `return` statement unwinding to its call
+pub static mut RETURN_VALUE: Option<Value> = None;
+/// The function and arguments of a tail call, made by the call it returns from
+pub static mut TAIL_CALL: Option<(Value, Vec<Value>)> = None;
+/// The value of the `yield` statement unwinding to the generator it suspends
+pub static mut YIELD_VALUE: Option<Value> = None;
+pub static BUILTINS: [(&str, &Builtin); 17] = [
+    ("clock", &CLOCK),
+    ("str", &STR),
+    ("len", &LEN),
+    ("num", &NUM),
+    ("slice", &SLICE),
+    ("range", &RANGE),
+    ("input", &INPUT),
+    ("write", &WRITE),
+    ("format", &FORMAT),
+    ("gc", &GC),
+    ("memory", &MEMORY),
+    ("type", &TYPE),
+    ("class_of", &CLASS_OF),
+    ("fields", &FIELDS),
+    ("has_field", &HAS_FIELD),
+    ("get_field", &GET_FIELD),
+    ("set_field", &SET_FIELD),
+];
+/// Builtins that touch the file system, they are not defined when running in sandbox
+pub static FILE_BUILTINS: [(&str, &Builtin); 7] = [
+    ("read_file", &READ_FILE),
+    ("write_file", &WRITE_FILE),
+    ("append_file", &APPEND_FILE),
+    ("read_lines", &READ_LINES),
+    ("file_exists", &FILE_EXISTS),
+    ("list_dir", &LIST_DIR),
+    ("remove_file", &REMOVE_FILE),
+];
+/// Disable the file system builtins
+pub static mut SANDBOX: bool = false;
+pub static mut ENGINE: Engine = Engine::Tree;
+/// Fold constants and prune dead code before running
+pub static mut OPTIMIZE: bool = true;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Engine {
+    /// Walk the AST
+    Tree,
+    /// Compile to bytecode and run it on the vm
+    Vm,
+}
+
+/// Run a script file, its imports are relative to its directory
+pub fn run_file(path: &str) -> Result<(), Error> {
+    run_on_interpreter_thread(|| {
+        let _script = module::set_script(path);
+        let contents =
+            fs::read_to_string(path).map_err(|e| Error::new(0, path.to_string(), e.to_string()))?;
+        run(&contents)
+    })
+}
+
+/// Read and run lines from stdin until it ends, printing the errors
+pub fn run_prompt() {
+    // Ctrl-C stops the input being run and returns to the prompt
+    interrupt::catch_ctrl_c();
+    let handle_in = std::io::stdin();
+    let mut handle_out = std::io::stdout();
+    loop {
+        print!(">>> ");
+        handle_out.flush().unwrap();
+        let mut input = String::new();
+        handle_in.read_line(&mut input).unwrap();
+        if input.is_empty() {
+            break;
+        }
+        if let Err(e) = run(&input) {
+            println!("{e}");
+        }
+    }
+}
+
+/// Run source in the global environment, the definitions stay for the following runs. It
+/// runs on the interpreter thread, whatever the stack of the calling thread.
+pub fn run(source: &str) -> Result<(), Error> {
+    run_on_interpreter_thread(|| {
+        limits::reset();
+        interrupt::clear();
+        unsafe {
+            define_builtins(&ENVIRONMENT);
+        }
+        let ast = load(&source.to_string())?;
+        execute(&ast)
+    })
+}
+
+/// Scan, parse, resolve and optimize the source, for the current global environment
+pub fn load(source: &String) -> Result<Vec<Rc<dyn Stmt>>, Error> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens()?;
+
+    let mut parse = parser::Parser::new(tokens);
+    let ast = parse.parse()?;
+    let mut scopes = unsafe { Scopes::with_globals(&ENVIRONMENT.borrow()) };
+    for stmt in ast.clone() {
+        stmt.resolve(&mut scopes)?;
+    }
+    Ok(if unsafe { OPTIMIZE } {
+        ast::optimizer::optimize(ast)
+    } else {
+        ast
+    })
+}
+
+/// Run the statements in the current global environment, with the selected engine. Deep
+/// recursion needs the stack of `run_on_interpreter_thread`, which `run` uses.
+pub fn execute(ast: &[Rc<dyn Stmt>]) -> Result<(), Error> {
+    if unsafe { ENGINE } == Engine::Vm {
+        let script = vm::Compiler::compile_script(ast)?;
+        return vm::Vm::new().interpret(script);
+    }
+    for stmt in ast {
+        // println!("{}", stmt);
+        stmt.interpret()?;
+    }
+    Ok(())
+}
+
+/// Define the builtins in the global environment, without the file system ones in the sandbox
+pub fn define_builtins(environment: &Rc<RefCell<Environment>>) {
+    let mut environment = environment.borrow_mut();
+    for builtin in BUILTINS.iter() {
+        define_builtin(&mut environment, builtin.0, builtin.1.clone());
+    }
+    for builtin in FILE_BUILTINS.iter() {
+        if unsafe { !SANDBOX } {
+            define_builtin(&mut environment, builtin.0, builtin.1.clone());
+        } else {
+            // defined by a run outside the sandbox, a global the script named so stays
+            let name = Symbol::intern(builtin.0);
+            if let Some(Value::Builtin(_)) = environment.get_global(name) {
+                environment.undefine(name);
+            }
+        }
+    }
+}
+
+fn define_builtin(environment: &mut Environment, name: &str, builtin: Builtin) {
+    environment.define_constant(Symbol::intern(name), Value::Builtin(Rc::new(builtin)));
+}
+
+/// Run the following scripts without the file system builtins and imports
+pub fn set_sandbox(enabled: bool) {
+    unsafe {
+        SANDBOX = enabled;
+    }
+}
+
+/// A handle to interrupt the running script from another thread
+pub fn interrupt_handle() -> InterruptHandle {
+    interrupt::handle()
+}
+
+/// Profile the following runs, `lines` also samples the executing line every millisecond
+pub fn start_profiler(lines: bool) {
+    profiler::start(lines);
+}
+
+/// Print the profile, and write the collapsed stacks to `output` if given
+pub fn report_profile(output: Option<&str>) {
+    profiler::report(output);
+}
+
+/// Limit the call depth, steps and heap size of the following runs. Exceeding a limit stops
+/// the run with an error.
+pub fn set_limits(limits: Limits) {
+    unsafe {
+        limits::LIMITS = limits;
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum FunctionType {
+    Function,
+    Method,
+    Initializer,
+    Generator,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ClassType {
+    Class,
+    SubClass,
+    /// The static members of a class, which have no `this`
+    Static,
+}
+
+/// Each scope maps a name to whether it is defined yet, its slot and whether it is a
+/// constant, followed by the constant globals
+pub struct Scopes(
+    Vec<HashMap<String, (bool, usize, bool)>>,
+    Option<FunctionType>,
+    Option<ClassType>,
+    HashSet<String>,
+);
+
+impl Scopes {
+    pub fn new() -> Self {
+        Self(Vec::new(), None, None, HashSet::new())
+    }
+
+    /// Scopes over the constants already defined in the global environment, like the builtins
+    pub fn with_globals(globals: &Environment) -> Self {
+        let mut scopes = Self::new();
+        scopes.3 = globals
+            .constants()
+            .map(|name| name.as_str().to_string())
+            .collect();
+        scopes
+    }
+
+    pub fn begin_scope(&mut self) {
+        self.0.push(HashMap::new());
+    }
+
+    pub fn end_scope(&mut self) {
+        self.0.pop();
+    }
+
+    pub fn declare(&mut self, name: Token) -> Result<(), Error> {
+        self.declare_variable(name, false)
+    }
+
+    /// Declare a variable that can't be assigned once defined
+    pub fn declare_constant(&mut self, name: Token) -> Result<(), Error> {
+        self.declare_variable(name, true)
+    }
+
+    fn declare_variable(&mut self, name: Token, constant: bool) -> Result<(), Error> {
+        let Some(scope) = self.0.last_mut() else {
+            if self.3.contains(&name.lexeme) {
+                return Err(Error::new(
+                    name.line,
+                    name.lexeme.clone(),
+                    "Can't redeclare a constant".to_string(),
+                ));
+            }
+            if constant {
+                self.3.insert(name.lexeme);
+            }
+            return Ok(());
+        };
+        if scope.contains_key(&name.lexeme) {
+            return Err(Error::new(
+                name.line,
+                name.lexeme.clone(),
+                "Variable with this name already declared in this scope".to_string(),
+            ));
+        }
+        let slot = scope.len();
+        scope.insert(name.lexeme, (false, slot, constant));
+        Ok(())
+    }
+
+    pub fn define(&mut self, name: Token) {
+        self.define_name(name.lexeme);
+    }
+
+    /// Define a name the user can't declare, like `this` and `super`
+    pub fn define_name(&mut self, name: String) {
+        if let Some(scope) = self.0.last_mut() {
+            let (slot, constant) = scope
+                .get(&name)
+                .map_or((scope.len(), false), |(_, slot, constant)| {
+                    (*slot, *constant)
+                });
+            scope.insert(name, (true, slot, constant));
+        }
+    }
+
+    /// Fail if the variable the name refers to is a constant
+    pub fn check_assignable(&self, name: &Token) -> Result<(), Error> {
+        let constant = match self
+            .0
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme))
+        {
+            Some((_, _, constant)) => *constant,
+            None => self.3.contains(&name.lexeme),
+        };
+        if constant {
+            return Err(Error::new(
+                name.line,
+                name.lexeme.clone(),
+                "Can't assign to a constant".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn peek(&self) -> Option<&HashMap<String, (bool, usize, bool)>> {
+        self.0.last()
+    }
+
+    pub fn resolve_local(&self, expr: *const dyn Expr, name: &Token) {
+        for i in (0..self.0.len()).rev() {
+            if let Some((_, slot, _)) = self.0[i].get(&name.lexeme) {
+                unsafe {
+                    LOCALS.insert(expr, (self.0.len() - 1 - i, *slot));
+                }
+                return;
+            }
+        }
+    }
+
+    pub fn get_current_function(&self) -> Option<FunctionType> {
+        self.1
+    }
+
+    pub fn set_current_function(&mut self, function_type: Option<FunctionType>) {
+        self.1 = function_type;
+    }
+
+    pub fn get_current_class(&self) -> Option<ClassType> {
+        self.2
+    }
+
+    pub fn set_current_class(&mut self, class_type: Option<ClassType>) {
+        self.2 = class_type;
+    }
+}
@@ -0,0 +1,144 @@
+//! Execution limits, so a script can't crash or hang the process running it
+use crate::{Error, Token};
+
+/// Calls nested deeper than this are reported as a stack overflow, unless configured
+pub const DEFAULT_MAX_DEPTH: usize = 4096;
+/// The native stack of the interpreter thread, see `run_on_interpreter_thread`
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+/// Native stack kept free when checking the depth of a call
+const STACK_MARGIN: usize = 4 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// The deepest nesting of calls
+    pub max_depth: usize,
+    /// The most steps a run can take, each loop iteration and each call is a step
+    pub max_steps: Option<u64>,
+    /// The most bytes the heap may grow to, checked at each step. It needs `Counting` as the
+    /// global allocator, runs fail without it
+    pub max_heap: Option<usize>,
+}
+
+impl Limits {
+    pub const DEFAULT: Limits = Limits {
+        max_depth: DEFAULT_MAX_DEPTH,
+        max_steps: None,
+        max_heap: None,
+    };
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+pub static mut LIMITS: Limits = Limits::DEFAULT;
+static mut DEPTH: usize = 0;
+static mut STEPS: u64 = 0;
+/// Calls stop before the native stack grows below this address, 0 if unknown
+static mut STACK_END: usize = 0;
+
+/// Reset the counters before running a new piece of source
+pub fn reset() {
+    unsafe {
+        DEPTH = 0;
+        STEPS = 0;
+    }
+}
+
+fn limit_error(line: usize, loc: String, message: &str) -> Error {
+    Error::new(line, loc, message.to_string())
+}
+
+/// Count a step, and check the step and heap limits
+#[inline]
+pub fn step() -> Result<(), Error> {
+    unsafe {
+        STEPS += 1;
+        if LIMITS.max_steps.is_some_and(|max| STEPS > max) {
+            return Err(limit_error(0, "".to_string(), "Step limit exceeded"));
+        }
+        if let Some(max) = LIMITS.max_heap {
+            match crate::allocator::allocated() {
+                Some(allocated) if allocated > max => {
+                    return Err(limit_error(0, "".to_string(), "Memory limit exceeded"));
+                }
+                Some(_) => {}
+                None => {
+                    return Err(limit_error(
+                        0,
+                        "".to_string(),
+                        "Memory limit unavailable without the Counting allocator",
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Enter a call of the tree engine, which recurses on the native stack
+pub fn enter_call(name: &Token) -> Result<(), Error> {
+    unsafe {
+        let here = &name as *const _ as usize;
+        if DEPTH >= LIMITS.max_depth || here < STACK_END {
+            return Err(limit_error(
+                name.line,
+                name.lexeme.clone(),
+                "Stack overflow",
+            ));
+        }
+        DEPTH += 1;
+    }
+    Ok(())
+}
+
+pub fn exit_call() {
+    unsafe {
+        DEPTH -= 1;
+    }
+}
+
+/// Run `f` on a thread with a large stack, so deep recursion hits the depth limit or
+/// the stack check of `enter_call` instead of overflowing. Runs `f` right away when called
+/// from that thread already.
+pub fn run_on_interpreter_thread<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    if unsafe { STACK_END } != 0 {
+        return f();
+    }
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, move || {
+                let base = 0u8;
+                unsafe {
+                    STACK_END =
+                        (&base as *const u8 as usize).saturating_sub(STACK_SIZE - STACK_MARGIN);
+                }
+                let result = f();
+                // the stack of the next thread is elsewhere
+                unsafe {
+                    STACK_END = 0;
+                }
+                result
+            })
+            .expect("Could not start the interpreter thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// Parse a size in bytes, with an optional `K`, `M` or `G` suffix
+pub fn parse_size(size: &str) -> Result<usize, String> {
+    let (digits, unit) = match size.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&size[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&size[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    digits
+        .parse::<usize>()
+        .map(|n| n * unit)
+        .map_err(|e| e.to_string())
+}
@@ -1,201 +1,65 @@
-#![deny(unused_must_use)]
-use ast::expr::Expr;
-use ast::stmt::function::Builtin;
-use ast::value::Value;
 use clap::Parser;
-use environment::Environment;
-use error::Error;
-use once_cell::sync::Lazy;
-use scanner::Scanner;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::fs;
-use std::io::Write;
-use std::rc::Rc;
-use token::Token;
-use token_type::TokenType;
+use rlox::{run_file, run_prompt, set_limits, set_sandbox, Counting, Engine, Limits};
 
-mod ast;
-mod builtins;
-mod environment;
-mod error;
-mod parser;
-mod scanner;
-mod token;
-mod token_type;
-extern crate rlox_macro;
-
-pub use builtins::*;
-
-pub static mut ENVIRONMENT: Lazy<Rc<RefCell<Environment>>> =
-    Lazy::new(|| Rc::new(RefCell::new(Environment::new(None))));
-pub static mut LOCALS: Lazy<HashMap<*const dyn Expr, usize>> = Lazy::new(|| HashMap::new());
-pub static BUILTINS: [(&str, &Builtin); 5] = [
-    ("clock", &CLOCK),
-    ("str", &STR),
-    ("len", &LEN),
-    ("num", &NUM),
-    ("input", &INPUT),
-];
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Arg {
     pub script: Option<String>,
+    /// Run without access to the file system
+    #[arg(long)]
+    pub sandbox: bool,
+    /// The execution engine
+    #[arg(long, value_enum, default_value_t = Engine::Tree)]
+    pub engine: Engine,
+    /// The optimization level, 0 runs the code as written
+    #[arg(short = 'O', value_name = "LEVEL", default_value_t = 1)]
+    pub optimize: u8,
+    /// Print the calls and time of each function at exit
+    #[arg(long)]
+    pub profile: bool,
+    /// With --profile, also sample the executing line every millisecond
+    #[arg(long)]
+    pub profile_lines: bool,
+    /// With --profile, write the collapsed stacks for flamegraph tools to the file
+    #[arg(long, value_name = "FILE")]
+    pub profile_output: Option<String>,
+    /// The deepest nesting of calls before a stack overflow error
+    #[arg(long, value_name = "CALLS", default_value_t = rlox::DEFAULT_MAX_DEPTH)]
+    pub max_depth: usize,
+    /// Stop after this many steps, each loop iteration and each call is a step
+    #[arg(long, value_name = "STEPS")]
+    pub max_steps: Option<u64>,
+    /// Stop when the heap grows past this size, in bytes or with a K, M or G suffix
+    #[arg(long, value_name = "SIZE", value_parser = rlox::parse_size)]
+    pub max_heap: Option<usize>,
 }
 
 fn main() {
     let args = Arg::parse();
-    if let Some(script) = args.script {
-        run_file(script);
-    } else {
-        run_prompt();
-    }
-}
-
-fn run_file(script: String) {
-    let contents = fs::read_to_string(script).expect("Something went wrong reading the file");
-    if let Err(e) = run(contents) {
-        println!("{e}");
-    }
-}
-
-fn run_prompt() {
-    let handle_in = std::io::stdin();
-    let mut handle_out = std::io::stdout();
-    loop {
-        print!(">>> ");
-        handle_out.flush().unwrap();
-        let mut input = String::new();
-        handle_in.read_line(&mut input).unwrap();
-        if input.is_empty() {
-            break;
-        }
-        if let Err(e) = run(input) {
-            println!("{e}");
-        }
-    }
-}
-
-fn run(source: String) -> Result<(), Error> {
-    for builtin in BUILTINS.iter() {
-        define_builtin(builtin.0, builtin.1.clone());
-    }
-
-    let mut scanner = Scanner::new(&source);
-    let tokens = scanner.scan_tokens()?;
-
-    let mut parse = parser::Parser::new(tokens);
-    let ast = parse.parse()?;
-    let mut scopes = Scopes::new();
-    for stmt in ast.clone() {
-        stmt.resolve(&mut scopes)?;
-    }
-    for stmt in ast {
-        // println!("{}", stmt);
-        stmt.interpret()?;
-    }
-    Ok(())
-}
-
-fn define_builtin(name: &str, builtin: Builtin) {
+    set_sandbox(args.sandbox);
+    set_limits(Limits {
+        max_depth: args.max_depth,
+        max_steps: args.max_steps,
+        max_heap: args.max_heap,
+    });
     unsafe {
-        ENVIRONMENT
-            .borrow_mut()
-            .define(name.to_string(), Box::new(Value::Builtin(Rc::new(builtin))));
-    }
-}
-
-#[derive(Clone, Copy)]
-pub enum FunctionType {
-    Function,
-    Method,
-    Initializer,
-}
-
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub enum ClassType {
-    Class,
-    SubClass,
-}
-
-pub struct Scopes(
-    Vec<HashMap<String, bool>>,
-    Option<FunctionType>,
-    Option<ClassType>,
-);
-
-impl Scopes {
-    pub fn new() -> Self {
-        Self(Vec::new(), None, None)
-    }
-
-    pub fn begin_scope(&mut self) {
-        self.0.push(HashMap::new());
+        rlox::ENGINE = args.engine;
+        rlox::OPTIMIZE = args.optimize > 0;
     }
-
-    pub fn end_scope(&mut self) {
-        self.0.pop();
+    if args.profile || args.profile_lines || args.profile_output.is_some() {
+        rlox::start_profiler(args.profile_lines);
     }
-
-    pub fn declare(&mut self, name: Token) -> Result<(), Error> {
-        if !self.0.is_empty() {
-            let scope = self.0.last_mut().unwrap();
-            if scope.contains_key(&name.lexeme) {
-                return Err(Error::new(
-                    name.line,
-                    name.lexeme.clone(),
-                    "Variable with this name already declared in this scope".to_string(),
-                ));
+    rlox::run_on_interpreter_thread(move || {
+        if let Some(script) = args.script {
+            if let Err(e) = run_file(&script) {
+                println!("{e}");
             }
-            scope.insert(name.lexeme, false);
-        }
-        Ok(())
-    }
-
-    pub fn define(&mut self, name: Token) {
-        if !self.0.is_empty() {
-            let scope = self.0.last_mut().unwrap();
-            scope.insert(name.lexeme, true);
+        } else {
+            run_prompt();
         }
-    }
-
-    pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
-    }
-
-    pub fn peek(&self) -> Option<&HashMap<String, bool>> {
-        self.0.last()
-    }
-
-    pub fn peek_mut(&mut self) -> Option<&mut HashMap<String, bool>> {
-        self.0.last_mut()
-    }
-
-    pub fn resolve_local(&self, expr: *const dyn Expr, name: &Token) {
-        for i in (0..self.0.len()).rev() {
-            if self.0[i].contains_key(&name.lexeme) {
-                unsafe {
-                    LOCALS.insert(expr, self.0.len() - 1 - i);
-                }
-                return;
-            }
-        }
-    }
-
-    pub fn get_current_function(&self) -> Option<FunctionType> {
-        self.1
-    }
-
-    pub fn set_current_function(&mut self, function_type: Option<FunctionType>) {
-        self.1 = function_type;
-    }
-
-    pub fn get_current_class(&self) -> Option<ClassType> {
-        self.2
-    }
-
-    pub fn set_current_class(&mut self, class_type: Option<ClassType>) {
-        self.2 = class_type;
-    }
+        rlox::report_profile(args.profile_output.as_deref());
+    });
 }
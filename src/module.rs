@@ -0,0 +1,185 @@
+//! Modules, scripts loaded by `import "path.lox" as name;`
+//!
+//! A module runs once, in its own global environment, and the importer gets a namespace
+//! whose members are the top-level definitions of the module, as they are now. The path is looked up relative to the
+//! directory of the importing script, then in each directory of `RLOX_PATH`.
+use crate::ast::stmt::Stmt;
+use crate::gc::Trace;
+use crate::interner::Symbol;
+use crate::{Environment, Error, Value};
+use once_cell::sync::Lazy;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+enum Module {
+    /// Being run, importing it again is a cycle
+    Loading,
+    /// The statements stay alive for the functions and the resolver entries they hold
+    Loaded {
+        namespace: Value,
+        _statements: Vec<Rc<dyn Stmt>>,
+    },
+}
+
+/// The value of `import "path" as name;`, a view of the globals of the module: a member
+/// assigned by a function of the module changes for the importer too
+pub struct Namespace {
+    pub name: String,
+    pub environment: Rc<RefCell<Environment>>,
+    /// The builtins the module was given, they aren't members unless it redefined them
+    builtins: HashMap<Symbol, Value>,
+}
+
+impl Namespace {
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        let value = self.environment.borrow().globals().get(&name).cloned()?;
+        (self.builtins.get(&name) != Some(&value)).then_some(value)
+    }
+
+    /// Assign a member, fails with the message of the error if it isn't one or is a constant
+    pub fn set(&self, name: Symbol, value: Value) -> Result<(), &'static str> {
+        if self.get(name).is_none() {
+            return Err("Undefined property");
+        }
+        Environment::assign_global(self.environment.clone(), name, value)
+    }
+
+    /// The names of the members
+    pub fn names(&self) -> Vec<Symbol> {
+        let environment = self.environment.borrow();
+        let globals = environment.globals();
+        globals
+            .iter()
+            .filter(|(name, value)| self.builtins.get(name) != Some(value))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+/// Tracked itself, its copies share the one reference it holds to the environment
+impl Trace for Namespace {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        visit(Rc::as_ptr(&self.environment) as *const ());
+        true
+    }
+}
+
+/// Modules by canonical path
+static mut MODULES: Lazy<HashMap<PathBuf, Module>> = Lazy::new(HashMap::new);
+/// The directories of the scripts being run, the last one imports relative to its own
+static mut DIRECTORIES: Vec<PathBuf> = Vec::new();
+
+/// Resolve the imports of the script at `path` relative to its directory, importing the
+/// script itself is circular. Both end when the returned guard is dropped.
+pub fn set_script(path: &str) -> Script {
+    let path = Path::new(path);
+    let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let file = path.canonicalize().ok();
+    unsafe {
+        (*std::ptr::addr_of_mut!(DIRECTORIES)).push(directory);
+        if let Some(file) = &file {
+            (*std::ptr::addr_of_mut!(MODULES)).insert(file.clone(), Module::Loading);
+        }
+    }
+    Script { file }
+}
+
+/// The script being run by `run_file`
+pub struct Script {
+    file: Option<PathBuf>,
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        unsafe {
+            (*std::ptr::addr_of_mut!(DIRECTORIES)).pop();
+            let modules = &mut *std::ptr::addr_of_mut!(MODULES);
+            if let Some(file) = &self.file {
+                if let Some(Module::Loading) = modules.get(file) {
+                    modules.remove(file);
+                }
+            }
+        }
+    }
+}
+
+/// The namespace of the module at `path`, run the first time it is imported
+pub fn import(path: &str, line: usize) -> Result<Value, Error> {
+    let error = |message: &str| Error::new(line, format!("at '{}'", path), message.to_string());
+    if unsafe { crate::SANDBOX } {
+        return Err(error("Imports are disabled in the sandbox"));
+    }
+    let file = find(path).ok_or_else(|| error("Cannot find module"))?;
+    let modules = unsafe { &mut *std::ptr::addr_of_mut!(MODULES) };
+    match modules.get(&file) {
+        Some(Module::Loaded { namespace, .. }) => return Ok(namespace.clone()),
+        Some(Module::Loading) => return Err(error("Circular import, the module is still loading")),
+        None => {}
+    }
+    modules.insert(file.clone(), Module::Loading);
+    match run(&file) {
+        Ok((namespace, statements)) => {
+            modules.insert(
+                file,
+                Module::Loaded {
+                    namespace: namespace.clone(),
+                    _statements: statements,
+                },
+            );
+            Ok(namespace)
+        }
+        Err(e) => {
+            modules.remove(&file);
+            Err(e)
+        }
+    }
+}
+
+/// The canonical path of the first file found for `path`
+fn find(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    let directories = unsafe { &*std::ptr::addr_of!(DIRECTORIES) };
+    let importer = directories.last().cloned().unwrap_or_default();
+    let search = std::env::var_os("RLOX_PATH")
+        .map(|paths| std::env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+    std::iter::once(importer)
+        .chain(search)
+        .map(|directory| directory.join(path))
+        .find(|file| file.is_file())
+        .and_then(|file| file.canonicalize().ok())
+}
+
+/// Run the module in a new global environment, the namespace refers to it
+fn run(file: &Path) -> Result<(Value, Vec<Rc<dyn Stmt>>), Error> {
+    let source = std::fs::read_to_string(file)
+        .map_err(|e| Error::new(0, file.display().to_string(), e.to_string()))?;
+    let environment = Rc::new(RefCell::new(Environment::new(None)));
+    crate::define_builtins(&environment);
+    let builtins = environment.borrow().globals().clone();
+    let statements = unsafe {
+        let directories = &mut *std::ptr::addr_of_mut!(DIRECTORIES);
+        directories.push(file.parent().map(Path::to_path_buf).unwrap_or_default());
+        let previous = std::mem::replace(&mut *crate::ENVIRONMENT, environment.clone());
+        let result = crate::load(&source).and_then(|statements| {
+            crate::execute(&statements)?;
+            Ok(statements)
+        });
+        *crate::ENVIRONMENT = previous;
+        directories.pop();
+        result
+    }?;
+
+    let name = file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let namespace = Value::Namespace(crate::gc::track(Rc::new(Namespace {
+        name,
+        environment,
+        builtins,
+    })));
+    Ok((namespace, statements))
+}
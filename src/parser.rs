@@ -1,11 +1,13 @@
 //! Parser
+use std::cell::Cell;
 use std::rc::Rc;
 
 use crate::ast::expr::*;
 use crate::ast::stmt::*;
 use crate::error::Error;
+use crate::interner::Symbol;
 use crate::token::Token;
-use crate::token_type::TokenType;
+use crate::token_type::{StringPart, TokenType};
 
 macro_rules! binary_loop {
     ($name: ident, $left: ident, $right: ident, $new_struct: ident, $op: path, $($operator: path),* ) => {
@@ -49,10 +51,15 @@ impl Parser {
         let result;
         if self.is_match(vec![TokenType::Var]) {
             result = self.var_declaration();
-        } else if self.is_match(vec![TokenType::Fun]) {
+        } else if self.is_match(vec![TokenType::Const, TokenType::Let]) {
+            result = self.constant_declaration();
+        } else if self.check(TokenType::Fun) && self.is_function_declaration() {
+            self.advance();
             result = self.function("function");
         } else if self.is_match(vec![TokenType::Class]) {
             result = self.class_declaration();
+        } else if self.is_match(vec![TokenType::Import]) {
+            result = self.import_declaration();
         } else {
             result = self.statement();
         }
@@ -62,12 +69,22 @@ impl Parser {
         result
     }
 
+    /// whether the `fun` at the current token declares a function, `fun name` or `fun* name`,
+    /// rather than starting an anonymous one
+    fn is_function_declaration(&self) -> bool {
+        let mut i = self.current + 1;
+        if self.tokens[i].token_type == TokenType::Star {
+            i += 1;
+        }
+        self.tokens[i].token_type == TokenType::Identifier(Symbol::EMPTY)
+    }
+
     pub fn class_declaration(&mut self) -> Result<Rc<dyn Stmt>, Error> {
-        let name = self.consume(TokenType::Identifier("".to_string()), "Expect class name")?;
+        let name = self.consume(TokenType::Identifier(Symbol::EMPTY), "Expect class name")?;
         let mut super_class = None;
         if self.is_match(vec![TokenType::Less]) {
             self.consume(
-                TokenType::Identifier("".to_string()),
+                TokenType::Identifier(Symbol::EMPTY),
                 "Expect superclass name",
             )?;
             super_class = Some(Rc::new(VarExpr {
@@ -76,23 +93,79 @@ impl Parser {
         }
         self.consume(TokenType::LeftBrace, "Expect '{' before class body")?;
         let mut methods = Vec::new();
+        let mut static_methods = Vec::new();
+        let mut static_fields = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_end() {
-            let fun = self.function("method")?;
-            let function = unsafe { Rc::from_raw(Rc::into_raw(fun) as *const Function) };
-            methods.push(function);
+            if !self.is_match(vec![TokenType::Class]) {
+                methods.push(self.method(&name, "method")?);
+            } else if self
+                .tokens
+                .get(self.current + 1)
+                .is_some_and(|token| token.token_type == TokenType::Equal)
+            {
+                let field = self.consume(
+                    TokenType::Identifier(Symbol::EMPTY),
+                    "Expect static field name",
+                )?;
+                self.advance();
+                let value = self.expression()?;
+                self.consume(TokenType::Semicolon, "Expect ';' after static field")?;
+                static_fields.push((field, value));
+            } else {
+                static_methods.push(self.method(&name, "static method")?);
+            }
         }
 
         self.consume(TokenType::RightBrace, "Expect '}' after class body")?;
         Ok(Rc::new(Class {
             name,
             methods,
+            static_methods,
+            static_fields,
             super_class,
         }))
     }
 
+    /// parse a method of the class, a getter if it has no parameter list, a generator if its
+    /// name follows a '*'
+    fn method(&mut self, class: &Token, kind: &str) -> Result<Rc<Function>, Error> {
+        let is_generator = self.is_match(vec![TokenType::Star]);
+        let name = self.consume(
+            TokenType::Identifier(Symbol::EMPTY),
+            ("Expect ".to_string() + kind + " name, but find '" + &self.peek().lexeme + "'")
+                .as_str(),
+        )?;
+        if is_generator && name.lexeme == "init" {
+            return Err(Error::report(
+                name,
+                "An initializer can't be a generator".to_string(),
+            ));
+        }
+        let is_getter = !is_generator && self.check(TokenType::LeftBrace);
+        let mut params = Vec::new();
+        if !is_getter {
+            self.consume(
+                TokenType::LeftParen,
+                ("Expect '(' after ".to_string() + kind + " name").as_str(),
+            )?;
+            params = self.parameters()?;
+        }
+        let body = self.function_body(kind)?;
+        Ok(Rc::new(Function {
+            name,
+            params,
+            body,
+            is_initializer: false,
+            class: Some(class.symbol()),
+            is_getter,
+            is_generator,
+        }))
+    }
+
     pub fn function(&mut self, kind: &str) -> Result<Rc<dyn Stmt>, Error> {
+        let is_generator = self.is_match(vec![TokenType::Star]);
         let name = self.consume(
-            TokenType::Identifier("".to_string()),
+            TokenType::Identifier(Symbol::EMPTY),
             ("Expect ".to_string() + kind + " name, but find '" + &self.peek().lexeme + "'")
                 .as_str(),
         )?;
@@ -100,6 +173,21 @@ impl Parser {
             TokenType::LeftParen,
             ("Expect '(' after ".to_string() + kind + " name").as_str(),
         )?;
+        let params = self.parameters()?;
+        let body = self.function_body(kind)?;
+        Ok(Rc::new(Function {
+            name,
+            params,
+            body,
+            is_initializer: false,
+            class: None,
+            is_getter: false,
+            is_generator,
+        }))
+    }
+
+    /// parse the parameters after the '(' of a function, up to the ')'
+    fn parameters(&mut self) -> Result<Vec<Token>, Error> {
         let mut params = Vec::new();
         if !self.check(TokenType::RightParen) {
             while {
@@ -110,28 +198,24 @@ impl Parser {
                     ));
                 }
                 params.push(self.consume(
-                    TokenType::Identifier("".to_string()),
+                    TokenType::Identifier(Symbol::EMPTY),
                     "Expect parameter name",
                 )?);
                 self.is_match(vec![TokenType::Comma])
             } {}
         }
         self.consume(TokenType::RightParen, "Expect ')' after parameters")?;
+        Ok(params)
+    }
+
+    fn function_body(&mut self, kind: &str) -> Result<Rc<Block>, Error> {
         self.consume(
             TokenType::LeftBrace,
             ("Expect '{' before ".to_string() + kind + " body").as_str(),
         )?;
         let body = self.block()?;
         if body.type_name() == std::any::type_name::<Block>() {
-            unsafe {
-                let body_ptr = Rc::from_raw(Rc::into_raw(body) as *mut Block);
-                Ok(Rc::new(Function {
-                    name,
-                    params,
-                    body: body_ptr,
-                    is_initializer: false,
-                }))
-            }
+            unsafe { Ok(Rc::from_raw(Rc::into_raw(body) as *mut Block)) }
         } else {
             Err(Error::report(
                 self.peek(),
@@ -140,11 +224,92 @@ impl Parser {
         }
     }
 
+    /// parse an anonymous function after its `fun` or the '(' of its parameters
+    fn lambda(&mut self, keyword: Token, arrow: bool) -> Result<Rc<dyn Expr>, Error> {
+        let name = Token {
+            token_type: TokenType::Identifier(Symbol::intern("<lambda>")),
+            lexeme: "<lambda>".to_string(),
+            line: keyword.line,
+            column: keyword.column,
+        };
+        let is_generator = !arrow && self.is_match(vec![TokenType::Star]);
+        if !arrow {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'fun'")?;
+        }
+        let params = self.parameters()?;
+        let body = if !arrow {
+            self.function_body("function")?
+        } else {
+            let arrow = self.consume(TokenType::Arrow, "Expect '=>' after parameters")?;
+            if self.check(TokenType::LeftBrace) {
+                self.function_body("function")?
+            } else {
+                // the expression is the value returned
+                let value = self.expression()?;
+                Rc::new(Block {
+                    statements: vec![Rc::new(ReturnExpr {
+                        keyword: Token {
+                            token_type: TokenType::Return,
+                            lexeme: "return".to_string(),
+                            ..arrow
+                        },
+                        value: Some(value),
+                        tail_call: Cell::new(false),
+                    })],
+                })
+            }
+        };
+        Ok(Rc::new(Lambda {
+            function: Rc::new(Function {
+                name,
+                params,
+                body,
+                is_initializer: false,
+                class: None,
+                is_getter: false,
+                is_generator,
+            }),
+        }))
+    }
+
+    /// whether the '(' just matched starts the parameters of an arrow function
+    fn is_arrow(&self) -> bool {
+        let identifier = TokenType::Identifier(Symbol::EMPTY);
+        let mut i = self.current;
+        if self.tokens[i].token_type == identifier {
+            i += 1;
+            while self.tokens[i].token_type == TokenType::Comma
+                && self.tokens[i + 1].token_type == identifier
+            {
+                i += 2;
+            }
+        }
+        self.tokens[i].token_type == TokenType::RightParen
+            && self
+                .tokens
+                .get(i + 1)
+                .is_some_and(|token| token.token_type == TokenType::Arrow)
+    }
+
+    /// `import "path" as name;`
+    fn import_declaration(&mut self) -> Result<Rc<dyn Stmt>, Error> {
+        let keyword = self.previous();
+        let path = self.consume(TokenType::String(Symbol::EMPTY), "Expect module path")?;
+        let TokenType::String(path) = path.token_type else {
+            unreachable!()
+        };
+        self.consume(TokenType::As, "Expect 'as' after module path")?;
+        let name = self.consume(TokenType::Identifier(Symbol::EMPTY), "Expect module name")?;
+        self.consume(TokenType::Semicolon, "Expect ';' after import")?;
+        Ok(Rc::new(Import {
+            keyword,
+            path: path.as_str().to_string(),
+            name,
+        }))
+    }
+
     pub fn var_declaration(&mut self) -> Result<Rc<dyn Stmt>, Error> {
-        let name = self.consume(
-            TokenType::Identifier("".to_string()),
-            "Expect variable name",
-        )?;
+        let name = self.consume(TokenType::Identifier(Symbol::EMPTY), "Expect variable name")?;
 
         // handle variable declaration
         let initializer;
@@ -156,6 +321,7 @@ impl Parser {
                     token_type: TokenType::Nil,
                     lexeme: "".to_string(),
                     line: 0,
+                    column: 0,
                 },
             });
         }
@@ -166,11 +332,28 @@ impl Parser {
         Ok(Rc::new(VarDecl {
             name,
             initializer: Some(initializer),
+            constant: false,
+        }))
+    }
+
+    /// `const name = value;` or `let name = value;`, a variable that can't be assigned
+    fn constant_declaration(&mut self) -> Result<Rc<dyn Stmt>, Error> {
+        let name = self.consume(TokenType::Identifier(Symbol::EMPTY), "Expect constant name")?;
+        self.consume(TokenType::Equal, "Expect '=' after constant name")?;
+        let initializer = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after constant declaration",
+        )?;
+        Ok(Rc::new(VarDecl {
+            name,
+            initializer: Some(initializer),
+            constant: true,
         }))
     }
 
     pub fn statement(&mut self) -> Result<Rc<dyn Stmt>, Error> {
-        if self.is_match(vec![TokenType::Print]) {
+        if self.is_match(vec![TokenType::Print, TokenType::Eprint]) {
             self.print_statement()
         } else if self.is_match(vec![TokenType::While]) {
             self.while_statement()
@@ -182,6 +365,8 @@ impl Parser {
             self.for_statement()
         } else if self.is_match(vec![TokenType::Return]) {
             self.return_statement()
+        } else if self.is_match(vec![TokenType::Yield]) {
+            self.yield_statement()
         } else {
             self.expression_statement()
         }
@@ -194,11 +379,28 @@ impl Parser {
             value = Some(self.expression()?);
         }
         self.consume(TokenType::Semicolon, "Expect ';' after return value")?;
-        Ok(Rc::new(ReturnExpr { keyword, value }))
+        Ok(Rc::new(ReturnExpr {
+            keyword,
+            value,
+            tail_call: Cell::new(false),
+        }))
+    }
+
+    pub fn yield_statement(&mut self) -> Result<Rc<dyn Stmt>, Error> {
+        let keyword = self.previous();
+        let mut value = None;
+        if !self.check(TokenType::Semicolon) {
+            value = Some(self.expression()?);
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after yield value")?;
+        Ok(Rc::new(YieldExpr { keyword, value }))
     }
 
     pub fn for_statement(&mut self) -> Result<Rc<dyn Stmt>, Error> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'")?;
+        if self.is_for_in() {
+            return self.for_in_statement();
+        }
         let mut initializer = None;
         if self.is_match(vec![TokenType::Semicolon]) {
             self.consume(TokenType::Semicolon, "Expect ';' after for initializer")?;
@@ -239,6 +441,7 @@ impl Parser {
                     token_type: TokenType::True,
                     lexeme: "true".to_string(),
                     line: 0,
+                    column: 0,
                 },
             }));
         }
@@ -257,10 +460,49 @@ impl Parser {
         Ok(body)
     }
 
+    /// whether the '(' just matched starts the `[var] name in` of a `for in` loop
+    fn is_for_in(&self) -> bool {
+        let mut i = self.current;
+        if self.tokens[i].token_type == TokenType::Var {
+            i += 1;
+        }
+        self.tokens[i].token_type == TokenType::Identifier(Symbol::EMPTY)
+            && self
+                .tokens
+                .get(i + 1)
+                .is_some_and(|token| token.token_type == TokenType::In)
+    }
+
+    /// `for (var name in iterable) body`, the `var` is optional and the variable is always new
+    fn for_in_statement(&mut self) -> Result<Rc<dyn Stmt>, Error> {
+        self.is_match(vec![TokenType::Var]);
+        let name = self.consume(TokenType::Identifier(Symbol::EMPTY), "Expect variable name")?;
+        let keyword = self.consume(TokenType::In, "Expect 'in' after loop variable")?;
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses")?;
+        let body = self.statement()?;
+        Ok(Rc::new(ForIn {
+            name,
+            keyword,
+            iterable,
+            body: Rc::new(Block {
+                statements: vec![body],
+            }),
+        }))
+    }
+
     pub fn print_statement(&mut self) -> Result<Rc<dyn Stmt>, Error> {
-        let expr = self.expression()?;
+        let to_stderr = self.previous().token_type == TokenType::Eprint;
+        let mut expressions = Vec::new();
+        while {
+            expressions.push(self.expression()?);
+            self.is_match(vec![TokenType::Comma])
+        } {}
         self.consume(TokenType::Semicolon, "Expect ';' after value")?;
-        Ok(Rc::new(Print { expression: expr }))
+        Ok(Rc::new(Print {
+            expressions,
+            to_stderr,
+        }))
     }
 
     pub fn while_statement(&mut self) -> Result<Rc<dyn Stmt>, Error> {
@@ -308,7 +550,7 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<Rc<dyn Expr>, Error> {
-        let expr = self.or()?;
+        let expr = self.conditional()?;
         if self.is_match(vec![TokenType::Equal]) {
             let equals = self.previous();
             let value = self.assignment()?;
@@ -320,6 +562,12 @@ impl Parser {
                 }));
             } else if expr.type_name() == std::any::type_name::<crate::ast::expr::Get>() {
                 let expr_ptr = Rc::into_raw(expr) as *const crate::ast::expr::Get;
+                if unsafe { (*expr_ptr).optional } {
+                    return Err(Error::report(
+                        equals,
+                        "Invalid assignment target".to_string(),
+                    ));
+                }
                 return Ok(Rc::new(crate::ast::expr::Set {
                     object: unsafe { (*expr_ptr).object.clone() },
                     name: unsafe { (*expr_ptr).name.clone() },
@@ -357,6 +605,12 @@ impl Parser {
                 }
                 if name.type_name() == std::any::type_name::<crate::ast::expr::Get>() {
                     let name_ptr = Rc::into_raw(name) as *const crate::ast::expr::Get;
+                    if unsafe { (*name_ptr).optional } {
+                        return Err(Error::report(
+                            equals,
+                            "Invalid assignment target".to_string(),
+                        ));
+                    }
                     return Ok(Rc::new(crate::ast::expr::Set {
                         object: unsafe { (*name_ptr).object.clone() },
                         name: unsafe { (*name_ptr).name.clone() },
@@ -387,6 +641,29 @@ impl Parser {
         Ok(expr)
     }
 
+    /// `condition ? then : else`, right associative
+    fn conditional(&mut self) -> Result<Rc<dyn Expr>, Error> {
+        let condition = self.coalesce()?;
+        if self.is_match(vec![TokenType::Question]) {
+            let question = self.previous();
+            let then_branch = self.expression()?;
+            self.consume(
+                TokenType::Colon,
+                "Expect ':' after then branch of conditional",
+            )?;
+            let else_branch = self.conditional()?;
+            return Ok(Rc::new(Conditional {
+                condition,
+                question,
+                then_branch,
+                else_branch,
+            }));
+        }
+        Ok(condition)
+    }
+
+    binary_loop!(coalesce, or, or, Logic, TokenType::QuestionQuestion,);
+
     binary_loop!(or, and, and, Logic, TokenType::Or,);
 
     binary_loop!(and, equality, equality, Logic, TokenType::And,);
@@ -408,7 +685,8 @@ impl Parser {
         TokenType::Greater,
         TokenType::GreaterEqual,
         TokenType::Less,
-        TokenType::LessEqual
+        TokenType::LessEqual,
+        TokenType::InstanceOf
     );
 
     binary_loop!(
@@ -439,17 +717,26 @@ impl Parser {
         }
     }
 
+    /// parse a chain of calls, indexes and property accesses, wrapped in an `OptionalChain`
+    /// if a link is `?.`
     fn call(&mut self) -> Result<Rc<dyn Expr>, Error> {
         let mut expr = self.primary()?;
+        let mut is_optional = false;
         loop {
             if self.is_match(vec![TokenType::LeftParen]) {
                 expr = self.finish_call(expr)?;
-            } else if self.is_match(vec![TokenType::Dot]) {
+            } else if self.is_match(vec![TokenType::Dot, TokenType::QuestionDot]) {
+                let optional = self.previous().token_type == TokenType::QuestionDot;
+                is_optional |= optional;
                 let name = self.consume(
-                    TokenType::Identifier("".to_string()),
+                    TokenType::Identifier(Symbol::EMPTY),
                     "Expect property name after '.'",
                 )?;
-                expr = Rc::new(Get { object: expr, name });
+                expr = Rc::new(Get {
+                    object: expr,
+                    name,
+                    optional,
+                });
             } else if self.is_match(vec![TokenType::LeftBracket]) {
                 let index = self.expression()?;
                 self.consume(TokenType::RightBracket, "Expect ']' after index")?;
@@ -462,6 +749,9 @@ impl Parser {
                 break;
             }
         }
+        if is_optional {
+            expr = Rc::new(OptionalChain { expression: expr });
+        }
         Ok(expr)
     }
 
@@ -502,12 +792,19 @@ impl Parser {
             }))
         } else if self.is_match(vec![
             TokenType::Number(0.0),
-            TokenType::String("".to_string()),
+            TokenType::String(Symbol::EMPTY),
         ]) {
             Ok(Rc::new(Literal {
                 value: self.previous(),
             }))
+        } else if self.is_match(vec![TokenType::Interpolation(Vec::new())]) {
+            self.interpolation()
+        } else if self.is_match(vec![TokenType::Fun]) {
+            self.lambda(self.previous(), false)
         } else if self.is_match(vec![TokenType::LeftParen]) {
+            if self.is_arrow() {
+                return self.lambda(self.previous(), true);
+            }
             let expr = self.expression()?;
             self.consume(TokenType::RightParen, "Expect ')' after expression")?;
             Ok(Rc::new(Grouping { expression: expr }))
@@ -521,7 +818,7 @@ impl Parser {
             }
             self.consume(TokenType::RightBracket, "Expect ']' after array elements")?;
             Ok(Rc::new(Array { values }))
-        } else if self.is_match(vec![TokenType::Identifier("".to_string())]) {
+        } else if self.is_match(vec![TokenType::Identifier(Symbol::EMPTY)]) {
             let name = self.previous();
             Ok(Rc::new(VarExpr { name }))
         } else if self.is_match(vec![TokenType::This]) {
@@ -532,7 +829,7 @@ impl Parser {
             let keyword = self.previous();
             self.consume(TokenType::Dot, "Expect '.' after 'super'")?;
             let method = self.consume(
-                TokenType::Identifier("".to_string()),
+                TokenType::Identifier(Symbol::EMPTY),
                 "Expect superclass method name",
             )?;
             Ok(Rc::new(SuperExpr { keyword, method }))
@@ -544,6 +841,37 @@ impl Parser {
         }
     }
 
+    /// parse the embedded expressions of an interpolated string with their own parsers
+    fn interpolation(&mut self) -> Result<Rc<dyn Expr>, Error> {
+        let token = self.previous();
+        let mut parts: Vec<Rc<dyn Expr>> = Vec::new();
+        if let TokenType::Interpolation(string_parts) = token.token_type.clone() {
+            for part in string_parts {
+                match part {
+                    StringPart::Literal(s) => parts.push(Rc::new(Literal {
+                        value: Token {
+                            token_type: TokenType::String(Symbol::intern(&s)),
+                            lexeme: s,
+                            line: token.line,
+                            column: token.column,
+                        },
+                    })),
+                    StringPart::Expr(tokens) => {
+                        let mut parser = Parser::new(tokens);
+                        parts.push(parser.expression()?);
+                        if !parser.is_end() {
+                            return Err(Error::report(
+                                parser.peek(),
+                                "Expect '}' after interpolated expression".to_string(),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Rc::new(Interpolation { parts }))
+    }
+
     /// synchronize the state of parser when error happens
     fn synchronize(&mut self) {
         self.advance();
@@ -555,11 +883,16 @@ impl Parser {
                 TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
+                | TokenType::Const
+                | TokenType::Let
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
+                | TokenType::Eprint
+                | TokenType::Return
+                | TokenType::Yield
+                | TokenType::Import => return,
                 _ => {}
             }
             self.advance();
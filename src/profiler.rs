@@ -0,0 +1,289 @@
+//! Call profiler, enabled with `--profile`
+//!
+//! Calls are timed where they enter and leave a Lox function. Each call is recorded in a tree
+//! of call paths, so the profile can be written as collapsed stacks for flamegraph tools.
+use crate::interner::Symbol;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static mut PROFILER: Option<Profiler> = None;
+/// Set by the sampling thread, the interpreter records its line when it sees it
+static TICK: AtomicBool = AtomicBool::new(false);
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Identifies a Lox function or method in the profile
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FunctionId {
+    pub class: Option<Symbol>,
+    pub name: Symbol,
+    /// The line of the declaration, 0 for the script
+    pub line: usize,
+}
+
+impl std::fmt::Display for FunctionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(class) = self.class {
+            write!(f, "{}.", class)?;
+        }
+        if self.line > 0 {
+            write!(f, "{}:{}", self.name, self.line)
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    calls: u64,
+    /// Time between entering and leaving, counted once for recursive calls
+    inclusive: Duration,
+    /// Time not spent in the functions it called
+    own: Duration,
+    /// Calls still running, to not count the time of recursive calls twice
+    active: u32,
+}
+
+/// A call path, its parent is the path of the caller
+struct Node {
+    id: FunctionId,
+    parent: usize,
+    children: HashMap<FunctionId, usize>,
+    own: Duration,
+}
+
+struct Frame {
+    node: usize,
+    start: Instant,
+    /// Time spent in calls made by this frame
+    children: Duration,
+}
+
+struct Profiler {
+    functions: HashMap<FunctionId, Stats>,
+    /// Node 0 is the script
+    nodes: Vec<Node>,
+    stack: Vec<Frame>,
+    /// Samples per line, if line sampling is on
+    lines: Option<HashMap<usize, u64>>,
+}
+
+impl Profiler {
+    fn new(sample_lines: bool) -> Self {
+        let script = FunctionId {
+            class: None,
+            name: Symbol::intern("<script>"),
+            line: 0,
+        };
+        Self {
+            functions: HashMap::new(),
+            nodes: vec![Node {
+                id: script,
+                parent: 0,
+                children: HashMap::new(),
+                own: Duration::ZERO,
+            }],
+            stack: vec![Frame {
+                node: 0,
+                start: Instant::now(),
+                children: Duration::ZERO,
+            }],
+            lines: sample_lines.then(HashMap::new),
+        }
+    }
+
+    fn enter(&mut self, id: FunctionId) {
+        let parent = self.stack.last().map_or(0, |frame| frame.node);
+        let node = match self.nodes[parent].children.get(&id) {
+            Some(node) => *node,
+            None => {
+                self.nodes.push(Node {
+                    id,
+                    parent,
+                    children: HashMap::new(),
+                    own: Duration::ZERO,
+                });
+                let node = self.nodes.len() - 1;
+                self.nodes[parent].children.insert(id, node);
+                node
+            }
+        };
+        let stats = self.functions.entry(id).or_default();
+        stats.calls += 1;
+        stats.active += 1;
+        self.stack.push(Frame {
+            node,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    fn exit(&mut self) {
+        // the script frame stays until the report
+        if self.stack.len() <= 1 {
+            return;
+        }
+        let frame = self.stack.pop().unwrap();
+        let elapsed = frame.start.elapsed();
+        let own = elapsed.saturating_sub(frame.children);
+        let node = &mut self.nodes[frame.node];
+        node.own += own;
+        let stats = self.functions.get_mut(&node.id).unwrap();
+        stats.own += own;
+        stats.active -= 1;
+        if stats.active == 0 {
+            stats.inclusive += elapsed;
+        }
+        if let Some(caller) = self.stack.last_mut() {
+            caller.children += elapsed;
+        }
+    }
+
+    /// Close the frames of the calls still running
+    fn finish(&mut self) {
+        while self.stack.len() > 1 {
+            self.exit();
+        }
+        let script = &self.stack[0];
+        self.nodes[0].own = script.start.elapsed().saturating_sub(script.children);
+    }
+
+    fn table(&self) -> String {
+        let mut functions = self.functions.iter().collect::<Vec<_>>();
+        functions.sort_by(|a, b| b.1.own.cmp(&a.1.own).then(b.1.calls.cmp(&a.1.calls)));
+        let width = functions
+            .iter()
+            .map(|(id, _)| id.to_string().chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("function".len());
+        let mut table = format!(
+            "{:<width$} {:>10} {:>12} {:>12}\n",
+            "function", "calls", "total ms", "self ms"
+        );
+        for (id, stats) in functions {
+            let _ = writeln!(
+                table,
+                "{:<width$} {:>10} {:>12.3} {:>12.3}",
+                id.to_string(),
+                stats.calls,
+                stats.inclusive.as_secs_f64() * 1000.0,
+                stats.own.as_secs_f64() * 1000.0
+            );
+        }
+        if let Some(lines) = &self.lines {
+            let total = lines.values().sum::<u64>();
+            let mut lines = lines.iter().collect::<Vec<_>>();
+            lines.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            let _ = writeln!(table, "\n{:>8} {:>10} {:>8}", "line", "samples", "%");
+            for (line, samples) in lines {
+                let _ = writeln!(
+                    table,
+                    "{:>8} {:>10} {:>8.2}",
+                    line,
+                    samples,
+                    *samples as f64 * 100.0 / total as f64
+                );
+            }
+        }
+        table
+    }
+
+    /// One line per call path, the frames separated by `;` and followed by the self time in
+    /// microseconds
+    fn collapsed(&self) -> String {
+        let mut collapsed = String::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let micros = node.own.as_micros();
+            if micros == 0 {
+                continue;
+            }
+            let mut path = vec![node.id.to_string()];
+            let mut parent = index;
+            while parent != 0 {
+                parent = self.nodes[parent].parent;
+                path.push(self.nodes[parent].id.to_string());
+            }
+            path.reverse();
+            let _ = writeln!(collapsed, "{} {}", path.join(";"), micros);
+        }
+        collapsed
+    }
+}
+
+/// Start recording, and sample the executing line every millisecond if `sample_lines` is set
+pub fn start(sample_lines: bool) {
+    unsafe {
+        PROFILER = Some(Profiler::new(sample_lines));
+    }
+    if sample_lines {
+        std::thread::spawn(|| loop {
+            std::thread::sleep(SAMPLE_INTERVAL);
+            TICK.store(true, Ordering::Relaxed);
+        });
+    }
+}
+
+pub fn enter(id: FunctionId) {
+    if let Some(profiler) = unsafe { (*std::ptr::addr_of_mut!(PROFILER)).as_mut() } {
+        profiler.enter(id);
+    }
+}
+
+pub fn exit() {
+    if let Some(profiler) = unsafe { (*std::ptr::addr_of_mut!(PROFILER)).as_mut() } {
+        profiler.exit();
+    }
+}
+
+/// The number of calls running, to `unwind` to after an error
+pub fn depth() -> usize {
+    unsafe { (*std::ptr::addr_of!(PROFILER)).as_ref() }.map_or(0, |profiler| profiler.stack.len())
+}
+
+/// Leave the calls an error unwound without returning from them
+pub fn unwind(depth: usize) {
+    if let Some(profiler) = unsafe { (*std::ptr::addr_of_mut!(PROFILER)).as_mut() } {
+        while profiler.stack.len() > depth.max(1) {
+            profiler.exit();
+        }
+    }
+}
+
+/// Whether a line sample is due
+#[inline]
+pub fn ticked() -> bool {
+    TICK.load(Ordering::Relaxed)
+}
+
+#[inline]
+pub fn sample(line: usize) {
+    if ticked() {
+        record_line(line);
+    }
+}
+
+pub fn record_line(line: usize) {
+    TICK.store(false, Ordering::Relaxed);
+    if let Some(lines) = unsafe { (*std::ptr::addr_of_mut!(PROFILER)).as_mut() }
+        .and_then(|profiler| profiler.lines.as_mut())
+    {
+        *lines.entry(line).or_default() += 1;
+    }
+}
+
+/// Print the table to stderr, and write the collapsed stacks to `output` if given
+pub fn report(output: Option<&str>) {
+    let Some(profiler) = (unsafe { (*std::ptr::addr_of_mut!(PROFILER)).as_mut() }) else {
+        return;
+    };
+    profiler.finish();
+    eprint!("{}", profiler.table());
+    if let Some(output) = output {
+        if let Err(e) = std::fs::write(output, profiler.collapsed()) {
+            eprintln!("Could not write the profile to {}: {}", output, e);
+        }
+    }
+}
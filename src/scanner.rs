@@ -1,10 +1,12 @@
 //! Scanner for rlox
 use crate::error::Error;
+use crate::interner::Symbol;
 use crate::token::Token;
-use crate::token_type::TokenType;
+use crate::token_type::{StringPart, TokenType};
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::str::Chars;
+use unicode_xid::UnicodeXID;
 
 /// Map to identify keywords
 static KEYWORDS: Lazy<HashMap<&str, TokenType>> = Lazy::new(|| {
@@ -19,6 +21,7 @@ static KEYWORDS: Lazy<HashMap<&str, TokenType>> = Lazy::new(|| {
         ("nil", TokenType::Nil),
         ("or", TokenType::Or),
         ("print", TokenType::Print),
+        ("eprint", TokenType::Eprint),
         ("return", TokenType::Return),
         ("super", TokenType::Super),
         ("this", TokenType::This),
@@ -27,6 +30,13 @@ static KEYWORDS: Lazy<HashMap<&str, TokenType>> = Lazy::new(|| {
         ("while", TokenType::While),
         ("break", TokenType::Break),
         ("continue", TokenType::Continue),
+        ("instanceof", TokenType::InstanceOf),
+        ("in", TokenType::In),
+        ("yield", TokenType::Yield),
+        ("import", TokenType::Import),
+        ("as", TokenType::As),
+        ("const", TokenType::Const),
+        ("let", TokenType::Let),
     ])
 });
 
@@ -39,11 +49,16 @@ pub struct Scanner<'a> {
     /// The index of the char we current consider
     current: Position<'a>,
     line: usize,
+    /// The index of the first char of the current line
+    line_start: usize,
+    /// The column of the token we current consider
+    column: usize,
 }
 
 /// An iterator to handle the stream of source code.
 ///
-/// May support utf-8, but is not tested, so it's now only stable for ascii
+/// It walks the source by `char`, so all the indexes are counted in unicode scalar values
+/// rather than bytes
 #[derive(Clone)]
 pub struct Position<'a> {
     iter: Chars<'a>,
@@ -70,7 +85,7 @@ impl<'a> Position<'a> {
     ///
     /// And the method will consume the former iterator
     pub fn get_between(a: &mut Self, b: &mut Self) -> Option<String> {
-        if a.index == a.end || b.index == b.end {
+        if a.index == a.end && b.index == b.end {
             return None;
         }
         let mut ret = String::new();
@@ -123,12 +138,15 @@ impl<'a> Scanner<'a> {
             start: Position::new(source.chars(), 0, end),
             current: Position::new(source.chars(), 0, end),
             line: 1,
+            line_start: 0,
+            column: 1,
         }
     }
 
     pub fn scan_tokens(&'a mut self) -> Result<Vec<Token>, Error> {
         while !self.current.is_end() {
             self.start = self.current.clone();
+            self.column = self.current.index - self.line_start + 1;
             if let Err(e) = self.scan_token() {
                 return Err(e);
             }
@@ -152,6 +170,7 @@ impl<'a> Scanner<'a> {
             Some('+') => self.add_token(TokenType::Plus),
             Some(';') => self.add_token(TokenType::Semicolon),
             Some('*') => self.add_token(TokenType::Star),
+            Some(':') => self.add_token(TokenType::Colon),
             Some('!') => {
                 if self.is_match('=') {
                     self.add_token(TokenType::BangEqual)
@@ -162,10 +181,21 @@ impl<'a> Scanner<'a> {
             Some('=') => {
                 if self.is_match('=') {
                     self.add_token(TokenType::EqualEqual)
+                } else if self.is_match('>') {
+                    self.add_token(TokenType::Arrow)
                 } else {
                     self.add_token(TokenType::Equal)
                 }
             }
+            Some('?') => {
+                if self.is_match('?') {
+                    self.add_token(TokenType::QuestionQuestion)
+                } else if self.is_match('.') {
+                    self.add_token(TokenType::QuestionDot)
+                } else {
+                    self.add_token(TokenType::Question)
+                }
+            }
             Some('<') => {
                 if self.is_match('=') {
                     self.add_token(TokenType::LessEqual)
@@ -191,10 +221,15 @@ impl<'a> Scanner<'a> {
             }
             Some(' ') | Some('\r') | Some('\t') => {}
             Some('\n') => {
-                self.line += 1;
+                self.new_line();
             }
             Some('"') => {
-                if let Err(e) = self.take_string() {
+                let triple = self.current.peek() == '"' && self.current.peek_next() == '"';
+                if triple {
+                    self.current.next();
+                    self.current.next();
+                }
+                if let Err(e) = self.take_string(triple) {
                     return Err(e);
                 }
             }
@@ -203,14 +238,19 @@ impl<'a> Scanner<'a> {
                     if let Err(e) = self.take_number(c) {
                         return Err(e);
                     }
+                } else if c == 'r' && self.current.peek() == '"' {
+                    self.current.next();
+                    self.take_raw_string()?;
                 } else if is_valid_start(c) {
                     self.take_identifier(c);
                 } else {
-                    return Err(Error::new(
+                    let mut error = Error::new(
                         self.line,
-                        "".to_string(),
+                        format!("'{}'", c),
                         "Unexpected character.".to_string(),
-                    ));
+                    );
+                    error.column = self.column;
+                    return Err(error);
                 }
             }
             None => self.add_token(TokenType::Eof),
@@ -232,6 +272,7 @@ impl<'a> Scanner<'a> {
             token_type,
             lexeme: text,
             line: self.line,
+            column: self.column,
         })
     }
 
@@ -241,9 +282,15 @@ impl<'a> Scanner<'a> {
             token_type,
             lexeme: text,
             line: self.line,
+            column: self.column,
         });
     }
 
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current.index;
+    }
+
     fn is_match(&mut self, target: char) -> bool {
         if self.current.is_end() {
             return false;
@@ -256,15 +303,123 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    fn take_string(&mut self) -> Result<(), Error> {
+    /// Take a string literal after the opening quote, `triple` for `"""` strings that end
+    /// with `"""` and may contain single quotes
+    fn take_string(&mut self, triple: bool) -> Result<(), Error> {
         let mut value = "".to_string();
-        while self.current.peek() != '"' && !self.current.is_end() {
-            if self.current.peek() == '\n' {
-                self.line += 1;
+        let mut parts = Vec::new();
+        while !self.current.is_end() && !self.is_string_end(triple) {
+            match self.current.next().unwrap() {
+                '\n' => {
+                    self.new_line();
+                    value.push('\n');
+                }
+                '\\' => value.push(self.take_escape()?),
+                '$' if self.current.peek() == '{' => {
+                    self.current.next();
+                    if !value.is_empty() {
+                        parts.push(StringPart::Literal(std::mem::take(&mut value)));
+                    }
+                    parts.push(StringPart::Expr(self.take_interpolation()?));
+                }
+                c => value.push(c),
             }
-            if self.current.peek() != '"' {
-                value.push(self.current.next().unwrap());
+        }
+
+        if self.current.is_end() {
+            return Err(Error::new(
+                self.line,
+                "".to_string(),
+                "Unterminated string.".to_string(),
+            ));
+        }
+
+        self.current.next();
+        if triple {
+            self.current.next();
+            self.current.next();
+        }
+
+        if parts.is_empty() {
+            self.add_token_literal(TokenType::String(Symbol::intern(&value)));
+        } else {
+            if !value.is_empty() {
+                parts.push(StringPart::Literal(value));
             }
+            self.add_token_literal(TokenType::Interpolation(parts));
+        }
+        Ok(())
+    }
+
+    fn is_string_end(&self, triple: bool) -> bool {
+        if triple {
+            let mut tmp = self.current.clone();
+            tmp.next() == Some('"') && tmp.next() == Some('"') && tmp.next() == Some('"')
+        } else {
+            self.current.peek() == '"'
+        }
+    }
+
+    /// Take the escape sequence after a backslash
+    fn take_escape(&mut self) -> Result<char, Error> {
+        match self.current.next() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some('\\') => Ok('\\'),
+            Some('"') => Ok('"'),
+            Some('$') => Ok('$'),
+            Some('u') => {
+                if !self.is_match('{') {
+                    return Err(Error::new(
+                        self.line,
+                        "\\u".to_string(),
+                        "Expect '{' after '\\u'.".to_string(),
+                    ));
+                }
+                let mut hex = String::new();
+                while self.current.peek() != '}' && !self.current.is_end() {
+                    hex.push(self.current.next().unwrap());
+                }
+                if !self.is_match('}') {
+                    return Err(Error::new(
+                        self.line,
+                        format!("\\u{{{}", hex),
+                        "Unterminated unicode escape.".to_string(),
+                    ));
+                }
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or(Error::new(
+                        self.line,
+                        format!("\\u{{{}}}", hex),
+                        "Invalid unicode escape.".to_string(),
+                    ))
+            }
+            Some(c) => Err(Error::new(
+                self.line,
+                format!("\\{}", c),
+                "Unknown escape sequence.".to_string(),
+            )),
+            None => Err(Error::new(
+                self.line,
+                "".to_string(),
+                "Unterminated string.".to_string(),
+            )),
+        }
+    }
+
+    /// Take a raw string `r"..."`, which has no escapes and no interpolation
+    fn take_raw_string(&mut self) -> Result<(), Error> {
+        let mut value = "".to_string();
+        while self.current.peek() != '"' && !self.current.is_end() {
+            let c = self.current.next().unwrap();
+            if c == '\n' {
+                self.new_line();
+            }
+            value.push(c);
         }
 
         if self.current.is_end() {
@@ -277,10 +432,72 @@ impl<'a> Scanner<'a> {
 
         self.current.next();
 
-        self.add_token_literal(TokenType::String(value));
+        self.add_token_literal(TokenType::String(Symbol::intern(&value)));
         Ok(())
     }
 
+    /// Scan the expression of `${...}` with a nested scanner, the `${` is already consumed
+    fn take_interpolation(&mut self) -> Result<Vec<Token>, Error> {
+        let line = self.line;
+        let column = self.current.index - self.line_start;
+        let mut source = String::new();
+        let mut depth = 1;
+        let mut in_string = false;
+        loop {
+            match self.current.next() {
+                Some('"') => {
+                    in_string = !in_string;
+                    source.push('"');
+                }
+                Some('\\') if in_string => {
+                    source.push('\\');
+                    if let Some(c) = self.current.next() {
+                        source.push(c);
+                    }
+                }
+                Some('{') if !in_string => {
+                    depth += 1;
+                    source.push('{');
+                }
+                Some('}') if !in_string => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                    source.push('}');
+                }
+                Some(c) => {
+                    if c == '\n' {
+                        self.new_line();
+                    }
+                    source.push(c);
+                }
+                None => {
+                    return Err(Error::new(
+                        line,
+                        "".to_string(),
+                        "Unterminated interpolation.".to_string(),
+                    ))
+                }
+            }
+        }
+        let mut scanner = Scanner::new(&source);
+        scanner.line = line;
+        let mut tokens = scanner.scan_tokens().map_err(|mut e| {
+            if e.line == line && e.column > 0 {
+                e.column += column;
+            }
+            e
+        })?;
+        // the nested scanner counts columns from the start of the expression
+        for token in tokens.iter_mut() {
+            if token.line == line {
+                token.column += column;
+            }
+        }
+        Ok(tokens)
+    }
+
     fn take_number(&mut self, current: char) -> Result<(), Error> {
         let mut value = current.to_string();
 
@@ -308,15 +525,96 @@ impl<'a> Scanner<'a> {
         if let Some(token_type) = token_type {
             self.add_token(token_type.clone())
         } else {
-            self.add_token(TokenType::Identifier(value))
+            self.add_token(TokenType::Identifier(Symbol::intern(&value)))
         }
     }
 }
 
+/// Identifiers follow the unicode XID rules, plus `_` as a start
 fn is_valid_start(a: char) -> bool {
-    a.is_ascii_alphabetic() || a == '_'
+    a.is_xid_start() || a == '_'
 }
 
 fn is_valid(a: char) -> bool {
-    a.is_ascii_alphanumeric() || a == '_'
+    a.is_xid_continue()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// The interner is global, the tests scan one at a time
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    fn scan(source: &str) -> Result<Vec<Token>, Error> {
+        let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let source = source.to_string();
+        let mut scanner = Scanner::new(&source);
+        scanner.scan_tokens()
+    }
+
+    /// The lexeme and column of each token before the end
+    fn columns(source: &str) -> Vec<(String, usize)> {
+        let tokens = scan(source).unwrap();
+        tokens[..tokens.len() - 1]
+            .iter()
+            .map(|token| (token.lexeme.clone(), token.column))
+            .collect()
+    }
+
+    #[test]
+    fn cjk_identifiers() {
+        let tokens = scan("var 变量 = 値 + é_1;").unwrap();
+        for (i, name) in [(1, "变量"), (3, "値"), (5, "é_1")] {
+            assert!(matches!(
+                tokens[i].token_type,
+                TokenType::Identifier(symbol) if symbol.as_str() == name
+            ));
+            assert_eq!(tokens[i].lexeme, name);
+        }
+    }
+
+    #[test]
+    fn emoji_in_string_literals() {
+        let tokens = scan(r#""héllo 👋🏽" "日本""#).unwrap();
+        assert!(matches!(
+            tokens[0].token_type,
+            TokenType::String(symbol) if symbol.as_str() == "héllo 👋🏽"
+        ));
+        assert!(matches!(
+            tokens[1].token_type,
+            TokenType::String(symbol) if symbol.as_str() == "日本"
+        ));
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        assert_eq!(
+            columns("\"日本\" + x;"),
+            [
+                ("\"日本\"".to_string(), 1),
+                ("+".to_string(), 6),
+                ("x".to_string(), 8),
+                (";".to_string(), 9),
+            ]
+        );
+        // each line counts from its own start
+        assert_eq!(
+            columns("// 👋\nvar 名 = 1;"),
+            [
+                ("var".to_string(), 1),
+                ("名".to_string(), 5),
+                ("=".to_string(), 7),
+                ("1".to_string(), 9),
+                (";".to_string(), 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_column_after_multibyte_characters() {
+        let error = scan("var 名前 = \"👋\" # 1;").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 14));
+    }
 }
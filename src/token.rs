@@ -1,3 +1,4 @@
+use crate::interner::Symbol;
 use crate::token_type::TokenType;
 
 #[derive(Debug, Clone)]
@@ -5,6 +6,18 @@ pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: usize,
+    /// Column of the first character, counted in characters from 1, 0 for synthetic tokens
+    pub column: usize,
+}
+
+impl Token {
+    /// The interned name of an identifier, or of the lexeme of other tokens
+    pub fn symbol(&self) -> Symbol {
+        match self.token_type {
+            TokenType::Identifier(symbol) => symbol,
+            _ => Symbol::intern(&self.lexeme),
+        }
+    }
 }
 
 impl std::fmt::Display for Token {
@@ -1,4 +1,5 @@
-use crate::Value;
+use crate::interner::Symbol;
+use crate::{Token, Value};
 
 #[derive(Debug, Clone)]
 pub enum TokenType {
@@ -16,20 +17,32 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Colon,
 
     // One or two character tokens.
     Bang,
     BangEqual,
     Equal,
     EqualEqual,
+    /// `=>` of an arrow function
+    Arrow,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
+    /// `?` of a conditional
+    Question,
+    /// `??`, the right side unless the left one is not nil
+    QuestionQuestion,
+    /// `?.`, a property of an object that can be nil
+    QuestionDot,
 
     // Literals.
-    Identifier(String),
-    String(String),
+    Identifier(Symbol),
+    /// Equal string constants share the interned storage
+    String(Symbol),
+    /// String literal containing `${...}`
+    Interpolation(Vec<StringPart>),
     Number(f64),
 
     // Keywords.
@@ -43,6 +56,7 @@ pub enum TokenType {
     Nil,
     Or,
     Print,
+    Eprint,
     Return,
     Super,
     This,
@@ -51,26 +65,42 @@ pub enum TokenType {
     While,
     Break,
     Continue,
+    InstanceOf,
+    In,
+    Yield,
+    Import,
+    As,
+    Const,
+    Let,
 
     Eof,
 }
 
+/// A piece of an interpolated string literal
+#[derive(Debug, Clone)]
+pub enum StringPart {
+    Literal(String),
+    /// Tokens of an embedded expression, ended with `Eof`
+    Expr(Vec<Token>),
+}
+
 impl PartialEq for TokenType {
     fn eq(&self, other: &Self) -> bool {
-        unsafe { *(self as *const Self as *const isize) == *(other as *const Self as *const isize) }
+        // token types match by kind, ignoring the payload
+        std::mem::discriminant(self) == std::mem::discriminant(other)
     }
 }
 
 impl Eq for TokenType {}
 
 impl TokenType {
-    pub fn value(&self) -> Option<Box<Value>> {
+    pub fn value(&self) -> Option<Value> {
         match self {
-            TokenType::Number(n) => Some(Box::new(Value::Number(*n))),
-            TokenType::String(s) => Some(Box::new(Value::String(s.clone()))),
-            TokenType::True => Some(Box::new(Value::Boolean(true))),
-            TokenType::False => Some(Box::new(Value::Boolean(false))),
-            TokenType::Nil => Some(Box::new(Value::Nil)),
+            TokenType::Number(n) => Some(Value::Number(*n)),
+            TokenType::String(s) => Some(Value::String(s.to_rc())),
+            TokenType::True => Some(Value::Boolean(true)),
+            TokenType::False => Some(Value::Boolean(false)),
+            TokenType::Nil => Some(Value::Nil),
             _ => None,
         }
     }
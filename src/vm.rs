@@ -0,0 +1,13 @@
+//! Bytecode engine, an alternative to walking the AST.
+//!
+//! The [`Compiler`] turns the parsed (and resolved) AST into a [`Chunk`] per function, and the
+//! [`Vm`] runs them on a value stack. Globals live in the global [`crate::Environment`], so both
+//! engines see the same builtins and a REPL session keeps its globals across lines.
+pub mod chunk;
+pub mod compiler;
+pub mod machine;
+pub mod object;
+pub use chunk::{Chunk, OpCode};
+pub use compiler::{Compile, Compiler};
+pub use machine::Vm;
+pub use object::{Closure, FunctionProto, Upvalue};
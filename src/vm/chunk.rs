@@ -0,0 +1,131 @@
+//! Bytecode container
+use crate::interner::Symbol;
+use crate::Value;
+
+/// Instructions of the vm, operands are indexes into the constant pool, stack slots, jump
+/// offsets or names
+#[derive(Debug, Clone, Copy)]
+pub enum OpCode {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u8),
+    SetLocal(u8),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    GetGlobal(Symbol),
+    DefineGlobal(Symbol),
+    DefineConstant(Symbol),
+    SetGlobal(Symbol),
+    GetProperty(Symbol),
+    SetProperty(Symbol),
+    /// `object.name[i]...[j] = value`, with the number of indexes
+    SetPropertyIndex(Symbol, u8),
+    GetSuper(Symbol),
+    GetIndex,
+    /// `name[i]...[j] = value` of a local, with the slot and the number of indexes
+    SetLocalIndex(Symbol, u8, u8),
+    /// `name[i]...[j] = value` of a captured variable, with the upvalue and the number of
+    /// indexes
+    SetUpvalueIndex(Symbol, u8, u8),
+    /// `name[i]...[j] = value` of a global, with the number of indexes
+    SetGlobalIndex(Symbol, u8),
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    InstanceOf,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print(u8),
+    EPrint(u8),
+    /// Concatenate the `Display` of the values
+    Interpolate(u8),
+    Array(u16),
+    Jump(u16),
+    /// Pop the condition of an `if`, which must be a boolean
+    JumpIfFalse(u16),
+    /// Pop the condition of a `while`, anything but `true` ends the loop
+    JumpUnlessTrue(u16),
+    JumpIfTrueOrPop(u16),
+    JumpIfFalseOrPop(u16),
+    /// Jump keeping the value on top of the stack unless it is nil, else pop it
+    JumpIfNotNilOrPop(u16),
+    /// Jump keeping the value on top of the stack if it is nil
+    JumpIfNil(u16),
+    Loop(u16),
+    /// Replace the value on top of the stack with the value a `for in` loop walks
+    Iterable,
+    /// Push the next element of the iterable in the slot, advancing the position in the slot
+    /// after it, or jump when there is none
+    ForNext(u8, u16),
+    Call(u8),
+    /// Call a closure in place of the frame returning its result, other callees are called
+    /// as with `Call`
+    TailCall(u8),
+    Closure(u16),
+    CloseUpvalue,
+    Return,
+    /// Suspend the generator running the frame, with the value on top of the stack as its
+    /// next element
+    Yield,
+    /// Push the namespace of the module at the path, running it the first time
+    Import(Symbol),
+    /// Build a class from the `(name, method)` pairs on the stack, followed by the pairs of
+    /// its static methods
+    Class {
+        name: Symbol,
+        methods: u8,
+        statics: u8,
+        has_super: bool,
+    },
+}
+
+/// A compiled function body
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub constants: Vec<Value>,
+    /// Run-length encoded line table, `(line, count of instructions)`
+    lines: Vec<(usize, usize)>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, op: OpCode, line: usize) -> usize {
+        self.code.push(op);
+        match self.lines.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => self.lines.push((line, 1)),
+        }
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// Get the source line of the instruction at `offset`
+    pub fn get_line(&self, offset: usize) -> usize {
+        let mut current = 0;
+        for (line, count) in &self.lines {
+            current += count;
+            if offset < current {
+                return *line;
+            }
+        }
+        0
+    }
+}
@@ -0,0 +1,381 @@
+//! Compile the AST into bytecode
+use crate::ast::stmt::Function;
+use crate::ast::{Expr, Stmt};
+use crate::vm::{Chunk, FunctionProto, OpCode};
+use crate::{Error, FunctionType, Token, Value};
+use std::rc::Rc;
+
+/// Emit the bytecode of an AST node, implemented by every expression and statement
+pub trait Compile {
+    fn compile(&self, compiler: &mut Compiler) -> Result<(), Error>;
+}
+
+struct Local {
+    name: String,
+    /// `None` until the variable is initialized
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+/// The state of the function being compiled
+struct FunctionState {
+    proto: FunctionProto,
+    locals: Vec<Local>,
+    scope_depth: usize,
+}
+
+impl FunctionState {
+    /// `function_type` is `None` for the top level script
+    fn new(name: String, params: Vec<String>, function_type: Option<FunctionType>) -> Self {
+        // slot 0 holds the callee, or the instance for methods
+        let slot_zero = match function_type {
+            Some(FunctionType::Method) | Some(FunctionType::Initializer) => "this",
+            _ => "",
+        };
+        Self {
+            proto: FunctionProto {
+                name,
+                params,
+                chunk: Chunk::new(),
+                functions: Vec::new(),
+                upvalues: Vec::new(),
+                is_initializer: matches!(function_type, Some(FunctionType::Initializer)),
+                is_getter: false,
+                is_generator: false,
+                profile: None,
+            },
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: Some(0),
+                is_captured: false,
+            }],
+            scope_depth: 0,
+        }
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<u8> {
+        self.locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u8)
+    }
+}
+
+pub struct Compiler {
+    states: Vec<FunctionState>,
+    /// The line of the node being compiled
+    line: usize,
+    /// The jumps of the `?.` links of each optional chain being compiled, to its end
+    chains: Vec<Vec<usize>>,
+}
+
+impl Compiler {
+    /// Compile the statements of a script into a function taking no arguments
+    pub fn compile_script(statements: &[Rc<dyn Stmt>]) -> Result<Rc<FunctionProto>, Error> {
+        let mut compiler = Self {
+            states: vec![FunctionState::new("script".to_string(), Vec::new(), None)],
+            line: 0,
+            chains: Vec::new(),
+        };
+        for statement in statements {
+            statement.compile(&mut compiler)?;
+        }
+        compiler.emit_return();
+        Ok(Rc::new(compiler.states.pop().unwrap().proto))
+    }
+
+    fn state(&self) -> &FunctionState {
+        self.states.last().unwrap()
+    }
+
+    fn state_mut(&mut self) -> &mut FunctionState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state_mut().proto.chunk
+    }
+
+    pub fn set_line(&mut self, line: usize) {
+        self.line = line;
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::new(self.line, "".to_string(), message.to_string())
+    }
+
+    pub fn emit(&mut self, op: OpCode) -> usize {
+        let line = self.line;
+        self.chunk().write(op, line)
+    }
+
+    pub fn make_constant(&mut self, value: Value) -> Result<u16, Error> {
+        let index = self.chunk().add_constant(value);
+        u16::try_from(index).map_err(|_| self.error("Too many constants in one chunk"))
+    }
+
+    pub fn emit_constant(&mut self, value: Value) -> Result<(), Error> {
+        let index = self.make_constant(value)?;
+        self.emit(OpCode::Constant(index));
+        Ok(())
+    }
+
+    /// Emit a jump with a placeholder offset, to be fixed by `patch_jump`
+    pub fn emit_jump(&mut self, op: fn(u16) -> OpCode) -> usize {
+        self.emit(op(0))
+    }
+
+    /// Compile an optional chain, its `?.` links jump to the end with the nil they found
+    pub fn optional_chain(&mut self, expression: &Rc<dyn Expr>) -> Result<(), Error> {
+        self.chains.push(Vec::new());
+        let result = expression.compile(self);
+        let jumps = self.chains.pop().unwrap_or_default();
+        result?;
+        for jump in jumps {
+            self.patch_jump(jump)?;
+        }
+        Ok(())
+    }
+
+    /// Jump to the end of the optional chain being compiled if the top of the stack is nil
+    pub fn emit_chain_jump(&mut self) {
+        let jump = self.emit_jump(OpCode::JumpIfNil);
+        if let Some(chain) = self.chains.last_mut() {
+            chain.push(jump);
+        }
+    }
+
+    /// Make the jump at `offset` land on the next instruction
+    pub fn patch_jump(&mut self, offset: usize) -> Result<(), Error> {
+        let jump = self.chunk().code.len() - offset - 1;
+        let jump = u16::try_from(jump).map_err(|_| self.error("Too much code to jump over"))?;
+        let op = &mut self.chunk().code[offset];
+        *op = match *op {
+            OpCode::Jump(_) => OpCode::Jump(jump),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(jump),
+            OpCode::JumpUnlessTrue(_) => OpCode::JumpUnlessTrue(jump),
+            OpCode::JumpIfTrueOrPop(_) => OpCode::JumpIfTrueOrPop(jump),
+            OpCode::JumpIfFalseOrPop(_) => OpCode::JumpIfFalseOrPop(jump),
+            OpCode::JumpIfNotNilOrPop(_) => OpCode::JumpIfNotNilOrPop(jump),
+            OpCode::JumpIfNil(_) => OpCode::JumpIfNil(jump),
+            OpCode::ForNext(slot, _) => OpCode::ForNext(slot, jump),
+            _ => unreachable!("patch a non-jump instruction"),
+        };
+        Ok(())
+    }
+
+    /// The position a later `emit_loop` jumps back to
+    pub fn loop_start(&mut self) -> usize {
+        self.chunk().code.len()
+    }
+
+    pub fn emit_loop(&mut self, loop_start: usize) -> Result<(), Error> {
+        let offset = self.chunk().code.len() + 1 - loop_start;
+        let offset = u16::try_from(offset).map_err(|_| self.error("Loop body too large"))?;
+        self.emit(OpCode::Loop(offset));
+        Ok(())
+    }
+
+    /// Return from the current function, initializers always return `this`
+    pub fn emit_return(&mut self) {
+        if self.state().proto.is_initializer {
+            self.emit(OpCode::GetLocal(0));
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.emit(OpCode::Return);
+    }
+
+    pub fn begin_scope(&mut self) {
+        self.state_mut().scope_depth += 1;
+    }
+
+    pub fn end_scope(&mut self) {
+        self.state_mut().scope_depth -= 1;
+        loop {
+            let state = self.state();
+            let is_captured = match state.locals.last() {
+                Some(local) if local.depth.is_none_or(|d| d > state.scope_depth) => {
+                    local.is_captured
+                }
+                _ => break,
+            };
+            if is_captured {
+                self.emit(OpCode::CloseUpvalue);
+            } else {
+                self.emit(OpCode::Pop);
+            }
+            self.state_mut().locals.pop();
+        }
+    }
+
+    fn is_global_scope(&self) -> bool {
+        self.states.len() == 1 && self.state().scope_depth == 0
+    }
+
+    /// Reserve a stack slot for a local variable, the value is expected to be pushed next
+    pub fn add_local(&mut self, name: &str) -> Result<(), Error> {
+        if self.state().locals.len() > u8::MAX as usize {
+            return Err(self.error("Too many local variables in function"));
+        }
+        self.state_mut().locals.push(Local {
+            name: name.to_string(),
+            depth: None,
+            is_captured: false,
+        });
+        Ok(())
+    }
+
+    /// Bind the value on top of the stack to a local the compiler uses, and return its slot
+    pub fn add_hidden_local(&mut self, name: &str) -> Result<u8, Error> {
+        self.add_local(name)?;
+        self.mark_initialized();
+        Ok((self.state().locals.len() - 1) as u8)
+    }
+
+    /// Declare the variable in the current scope, globals need no declaration
+    pub fn declare_variable(&mut self, name: &Token) -> Result<(), Error> {
+        if self.is_global_scope() {
+            return Ok(());
+        }
+        self.set_line(name.line);
+        self.add_local(&name.lexeme)
+    }
+
+    pub fn mark_initialized(&mut self) {
+        let state = self.state_mut();
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(state.scope_depth);
+        }
+    }
+
+    /// Bind the value on top of the stack to the declared variable
+    pub fn define_variable(&mut self, name: &Token) -> Result<(), Error> {
+        if self.is_global_scope() {
+            self.emit(OpCode::DefineGlobal(name.symbol()));
+        } else {
+            self.mark_initialized();
+        }
+        Ok(())
+    }
+
+    /// Bind the value on top of the stack to the declared constant, the resolver has checked
+    /// that local ones are not assigned
+    pub fn define_constant(&mut self, name: &Token) -> Result<(), Error> {
+        if self.is_global_scope() {
+            self.emit(OpCode::DefineConstant(name.symbol()));
+        } else {
+            self.mark_initialized();
+        }
+        Ok(())
+    }
+
+    fn add_upvalue(&mut self, level: usize, is_local: bool, index: u8) -> Result<u8, Error> {
+        let upvalues = &mut self.states[level].proto.upvalues;
+        if let Some(i) = upvalues.iter().position(|u| *u == (is_local, index)) {
+            return Ok(i as u8);
+        }
+        if upvalues.len() > u8::MAX as usize {
+            return Err(self.error("Too many closure variables in function"));
+        }
+        upvalues.push((is_local, index));
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    /// Find the variable in the functions enclosing the function at `level`
+    fn resolve_upvalue(&mut self, level: usize, name: &str) -> Result<Option<u8>, Error> {
+        if level == 0 {
+            return Ok(None);
+        }
+        if let Some(slot) = self.states[level - 1].resolve_local(name) {
+            self.states[level - 1].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(level, true, slot).map(Some);
+        }
+        if let Some(index) = self.resolve_upvalue(level - 1, name)? {
+            return self.add_upvalue(level, false, index).map(Some);
+        }
+        Ok(None)
+    }
+
+    pub fn get_variable(&mut self, name: &Token) -> Result<(), Error> {
+        self.set_line(name.line);
+        let level = self.states.len() - 1;
+        if let Some(slot) = self.state().resolve_local(&name.lexeme) {
+            self.emit(OpCode::GetLocal(slot));
+        } else if let Some(index) = self.resolve_upvalue(level, &name.lexeme)? {
+            self.emit(OpCode::GetUpvalue(index));
+        } else {
+            self.emit(OpCode::GetGlobal(name.symbol()));
+        }
+        Ok(())
+    }
+
+    /// Assign the value on top of the stack to the variable, the value stays on the stack
+    pub fn set_variable(&mut self, name: &Token) -> Result<(), Error> {
+        self.set_line(name.line);
+        let level = self.states.len() - 1;
+        if let Some(slot) = self.state().resolve_local(&name.lexeme) {
+            self.emit(OpCode::SetLocal(slot));
+        } else if let Some(index) = self.resolve_upvalue(level, &name.lexeme)? {
+            self.emit(OpCode::SetUpvalue(index));
+        } else {
+            self.emit(OpCode::SetGlobal(name.symbol()));
+        }
+        Ok(())
+    }
+
+    /// Assign the value below the indexes on the stack to the element of the array in the
+    /// variable, the value stays on the stack
+    pub fn set_variable_index(&mut self, name: &Token, count: u8) -> Result<(), Error> {
+        self.set_line(name.line);
+        let level = self.states.len() - 1;
+        let symbol = name.symbol();
+        if let Some(slot) = self.state().resolve_local(&name.lexeme) {
+            self.emit(OpCode::SetLocalIndex(symbol, slot, count));
+        } else if let Some(index) = self.resolve_upvalue(level, &name.lexeme)? {
+            self.emit(OpCode::SetUpvalueIndex(symbol, index, count));
+        } else {
+            self.emit(OpCode::SetGlobalIndex(symbol, count));
+        }
+        Ok(())
+    }
+
+    /// Compile the function body in a new state, and emit the closure creating it
+    pub fn function(
+        &mut self,
+        function: &Function,
+        function_type: FunctionType,
+    ) -> Result<(), Error> {
+        self.set_line(function.name.line);
+        let params = function
+            .params
+            .iter()
+            .map(|param| param.lexeme.clone())
+            .collect();
+        self.states.push(FunctionState::new(
+            function.name.lexeme.clone(),
+            params,
+            Some(function_type),
+        ));
+        self.state_mut().proto.profile = Some(function.profile_id());
+        self.state_mut().proto.is_getter = function.is_getter;
+        self.state_mut().proto.is_generator = function.is_generator;
+        self.begin_scope();
+        for param in &function.params {
+            self.add_local(&param.lexeme)?;
+            self.mark_initialized();
+        }
+        for statement in &function.body.statements {
+            statement.compile(self)?;
+        }
+        self.emit_return();
+        let proto = self.states.pop().unwrap().proto;
+
+        let functions = &mut self.state_mut().proto.functions;
+        functions.push(Rc::new(proto));
+        let index = u16::try_from(functions.len() - 1)
+            .map_err(|_| self.error("Too many functions in one chunk"))?;
+        self.emit(OpCode::Closure(index));
+        Ok(())
+    }
+}
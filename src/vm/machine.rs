@@ -0,0 +1,895 @@
+//! Stack based virtual machine
+use crate::ast::stmt::{Instance, LoxClass};
+use crate::ast::value::LoxCallable;
+use crate::generator::{Generator, GeneratorState};
+use crate::interner::Symbol;
+use crate::vm::{Closure, FunctionProto, OpCode, Upvalue};
+use crate::{Environment, Error, Token, TokenType, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// The stack index of slot 0
+    base: usize,
+}
+
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    /// Upvalues still pointing to the stack, ordered by slot
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// The generators resumed, the last one runs the frame a `yield` suspends
+    generators: Vec<Rc<Generator>>,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            generators: Vec::new(),
+        }
+    }
+
+    /// Run a compiled script
+    pub fn interpret(&mut self, function: Rc<FunctionProto>) -> Result<(), Error> {
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: Vec::new(),
+            globals: unsafe { crate::ENVIRONMENT.clone() },
+        });
+        self.stack.push(Value::Closure(closure.clone(), None));
+        let profile_depth = crate::profiler::depth();
+        let result = self
+            .call_value(Value::Closure(closure, None), 0)
+            .and_then(|_| self.run(0));
+        if result.is_err() {
+            crate::profiler::unwind(profile_depth);
+        }
+        result.map(|_| ())
+    }
+
+    /// Call a closure from native code and wait for its result
+    pub fn call(&mut self, callee: Value, arguments: Vec<Value>) -> Result<Value, Error> {
+        let depth = self.frames.len();
+        let argc = arguments.len();
+        self.stack.push(callee.clone());
+        self.stack.extend(arguments);
+        let profile_depth = crate::profiler::depth();
+        let result = self.call_value(callee, argc).and_then(|_| {
+            if self.frames.len() == depth {
+                // builtins and classes without initializer finish immediately
+                Ok(self.pop())
+            } else {
+                self.run(depth)
+            }
+        });
+        if result.is_err() {
+            crate::profiler::unwind(profile_depth);
+        }
+        result
+    }
+
+    /// Run a generator on top of the stack to its next `yield`, `None` once it has returned
+    pub fn resume(&mut self, generator: &Rc<Generator>) -> Result<Option<Value>, Error> {
+        let (closure, ip, slots, upvalues) = match generator.resume_vm()? {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        let depth = self.frames.len();
+        let base = self.stack.len();
+        self.stack.extend(slots);
+        // the variables captured from the frame go back on the stack
+        for (slot, upvalue) in upvalues {
+            let value = std::mem::replace(&mut *upvalue.borrow_mut(), Upvalue::Open(base + slot));
+            if let Upvalue::Closed(value) = value {
+                self.stack[base + slot] = value;
+            }
+            self.open_upvalues.push(upvalue);
+        }
+        if let Some(id) = closure.function.profile {
+            crate::profiler::enter(id);
+        }
+        self.frames.push(CallFrame { closure, ip, base });
+        self.generators.push(generator.clone());
+        let result = self.run(depth);
+        self.generators.pop();
+        match result {
+            // `yield` saved the frame, `return` left the generator running
+            Ok(value) if matches!(*generator.state.borrow(), GeneratorState::Vm { .. }) => {
+                Ok(Some(value))
+            }
+            result => {
+                generator.state.replace(GeneratorState::Done);
+                result.map(|_| None)
+            }
+        }
+    }
+
+    /// Execute until the frame count drops back to `depth`, and return the last result
+    fn run(&mut self, depth: usize) -> Result<Value, Error> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let op = frame.closure.function.chunk.code[frame.ip];
+            frame.ip += 1;
+            if crate::profiler::ticked() {
+                crate::profiler::record_line(self.line());
+            }
+            match op {
+                OpCode::Constant(index) => {
+                    let value =
+                        self.frame().closure.function.chunk.constants[index as usize].clone();
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Boolean(true)),
+                OpCode::False => self.stack.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[self.frame().base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    let index = self.frame().base + slot as usize;
+                    self.stack[index] = self.peek(0).clone();
+                }
+                OpCode::GetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetGlobal(name) => {
+                    let value = self.frame().closure.globals.borrow().get_global(name);
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.name_error(name, "Undefined variable")),
+                    }
+                }
+                OpCode::DefineGlobal(name) => {
+                    let value = self.pop();
+                    self.frame()
+                        .closure
+                        .globals
+                        .borrow_mut()
+                        .define(name, value);
+                }
+                OpCode::DefineConstant(name) => {
+                    let value = self.pop();
+                    self.frame()
+                        .closure
+                        .globals
+                        .borrow_mut()
+                        .define_constant(name, value);
+                }
+                OpCode::SetGlobal(name) => {
+                    let value = self.peek(0).clone();
+                    let globals = self.frame().closure.globals.clone();
+                    if let Err(message) = Environment::assign_global(globals, name, value) {
+                        return Err(self.name_error(name, message));
+                    }
+                }
+                OpCode::GetProperty(name) => {
+                    let value = match self.pop() {
+                        Value::Instance(instance) => {
+                            let value = instance.borrow().get(name, instance.clone());
+                            value
+                        }
+                        Value::Class(class) => class.get_static(name),
+                        value @ Value::Generator(_) => value.builtin_method(name),
+                        Value::Namespace(namespace) => namespace.get(name),
+                        _ => return Err(self.name_error(name, "Only instance have properties")),
+                    };
+                    match value {
+                        Some(value) => self.push_property(value)?,
+                        None => {
+                            return Err(Error::new(
+                                self.line(),
+                                format!("'{}'", name),
+                                "Undefined property".to_string(),
+                            ))
+                        }
+                    }
+                }
+                OpCode::SetProperty(name) => {
+                    let value = self.pop();
+                    match self.pop() {
+                        Value::Instance(instance) => {
+                            instance.borrow_mut().set(name, value.clone());
+                            self.stack.push(value);
+                        }
+                        Value::Class(class) => {
+                            class.set_static(name, value.clone());
+                            self.stack.push(value);
+                        }
+                        Value::Namespace(namespace) => {
+                            if let Err(message) = namespace.set(name, value.clone()) {
+                                return Err(self.name_error(name, message));
+                            }
+                            self.stack.push(value);
+                        }
+                        _ => return Err(self.name_error(name, "Only instance have fields")),
+                    }
+                }
+                OpCode::SetPropertyIndex(name, count) => {
+                    let token = self.name_token(name);
+                    let indeces = self.pop_values(count as usize);
+                    let value = self.pop();
+                    let object = self.pop();
+                    object.set_field_element(&token, &indeces, value.clone())?;
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper(name) => {
+                    let super_class = self.pop();
+                    if let Value::Instance(this) = self.pop() {
+                        match super_class.get_method(name) {
+                            Some(mut method) => {
+                                method.bind(this);
+                                self.push_property(method)?;
+                            }
+                            None => {
+                                return Err(self
+                                    .name_error(name, &format!("Undefined property '{}'", name)))
+                            }
+                        }
+                    } else {
+                        unreachable!()
+                    }
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let array = self.pop();
+                    let value = self.get_index(array, index)?;
+                    self.stack.push(value);
+                }
+                OpCode::SetLocalIndex(name, slot, count) => {
+                    let token = self.name_token(name);
+                    let indeces = self.pop_values(count as usize);
+                    let value = self.pop();
+                    // the slot holds the array, which is changed in place unless it is shared
+                    let slot = self.frame().base + slot as usize;
+                    self.stack[slot].set_element(&indeces, value.clone(), &token)?;
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalueIndex(name, index, count) => {
+                    let token = self.name_token(name);
+                    let indeces = self.pop_values(count as usize);
+                    let value = self.pop();
+                    let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                    let open = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => Some(*slot),
+                        Upvalue::Closed(_) => None,
+                    };
+                    match open {
+                        Some(slot) => {
+                            self.stack[slot].set_element(&indeces, value.clone(), &token)?
+                        }
+                        None => {
+                            // not borrowed while `__setindex__` methods run
+                            let closed = upvalue.replace(Upvalue::Closed(Value::Nil));
+                            let Upvalue::Closed(mut array) = closed else {
+                                unreachable!()
+                            };
+                            let result = array.set_element(&indeces, value.clone(), &token);
+                            upvalue.replace(Upvalue::Closed(array));
+                            result?;
+                        }
+                    }
+                    self.stack.push(value);
+                }
+                OpCode::SetGlobalIndex(name, count) => {
+                    let token = self.name_token(name);
+                    let indeces = self.pop_values(count as usize);
+                    let value = self.pop();
+                    // taken out of the globals while it is updated, like a local in its slot
+                    let globals = self.frame().closure.globals.clone();
+                    let mut array = Environment::replace_global(globals.clone(), name, Value::Nil)
+                        .map_err(|message| self.name_error(name, message))?;
+                    let result = array.set_element(&indeces, value.clone(), &token);
+                    let _ = Environment::replace_global(globals, name, array);
+                    result?;
+                    self.stack.push(value);
+                }
+                OpCode::Equal | OpCode::NotEqual => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let equal = a.equals(&b)?;
+                    self.stack
+                        .push(Value::Boolean(equal == matches!(op, OpCode::Equal)));
+                }
+                OpCode::Greater | OpCode::GreaterEqual | OpCode::Less | OpCode::LessEqual => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    // `a > b` is `b < a`, and `a >= b` is `!(a < b)`
+                    let less = match op {
+                        OpCode::Less | OpCode::GreaterEqual => a.less_method(&b),
+                        _ => b.less_method(&a),
+                    };
+                    if let Some(less) = less {
+                        let less = less?;
+                        self.stack.push(Value::Boolean(
+                            less == matches!(op, OpCode::Less | OpCode::Greater),
+                        ));
+                        continue;
+                    }
+                    let (operator, accepted): (&str, &[std::cmp::Ordering]) = match op {
+                        OpCode::Greater => (">", &[std::cmp::Ordering::Greater]),
+                        OpCode::GreaterEqual => (
+                            ">=",
+                            &[std::cmp::Ordering::Greater, std::cmp::Ordering::Equal],
+                        ),
+                        OpCode::Less => ("<", &[std::cmp::Ordering::Less]),
+                        _ => ("<=", &[std::cmp::Ordering::Less, std::cmp::Ordering::Equal]),
+                    };
+                    match a.cmp(&b) {
+                        Ok(ordering) => self.stack.push(Value::Boolean(
+                            ordering.is_some_and(|o| accepted.contains(&o)),
+                        )),
+                        Err(_) => {
+                            return Err(self.operator_error(
+                                operator,
+                                &format!("Binary operator {} only works with numbers", operator),
+                            ))
+                        }
+                    }
+                }
+                OpCode::InstanceOf => {
+                    let class = self.pop();
+                    let value = self.pop();
+                    match class {
+                        Value::Class(class) => {
+                            self.stack.push(Value::Boolean(value.instance_of(&class)))
+                        }
+                        _ => {
+                            return Err(self.operator_error(
+                                "instanceof",
+                                "Right operand of instanceof must be a class",
+                            ))
+                        }
+                    }
+                }
+                OpCode::Add => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    if let Some(value) = a.operator_method(Symbol::ADD, &b) {
+                        self.stack.push(value?);
+                        continue;
+                    }
+                    let value = (a + b).map_err(|_| {
+                        self.operator_error(
+                            "+",
+                            "Binary operator + only works with numbers , strings or arrays",
+                        )
+                    })?;
+                    self.stack.push(value);
+                }
+                OpCode::Subtract => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    if let Some(value) = a.operator_method(Symbol::SUB, &b) {
+                        self.stack.push(value?);
+                        continue;
+                    }
+                    let value = (a - b).map_err(|_| {
+                        self.operator_error("-", "Binary operator - only works with numbers")
+                    })?;
+                    self.stack.push(value);
+                }
+                OpCode::Multiply => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    if let Some(value) = a.operator_method(Symbol::MUL, &b) {
+                        self.stack.push(value?);
+                        continue;
+                    }
+                    let value = (a * b).map_err(|_| {
+                        self.operator_error("*", "Binary operator * only works with numbers")
+                    })?;
+                    self.stack.push(value);
+                }
+                OpCode::Divide => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    if let Some(value) = a.operator_method(Symbol::DIV, &b) {
+                        self.stack.push(value?);
+                        continue;
+                    }
+                    let value = (a / b).map_err(|_| {
+                        self.operator_error("/", "Binary operator / only works with numbers")
+                    })?;
+                    self.stack.push(value);
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    let value = (!value).map_err(|_| {
+                        self.operator_error("!", "Unary operator ! only works with boolean")
+                    })?;
+                    self.stack.push(value);
+                }
+                OpCode::Negate => {
+                    let value = self.pop();
+                    let value = (-value).map_err(|_| {
+                        self.operator_error("-", "Unary operator - only works with numbers")
+                    })?;
+                    self.stack.push(value);
+                }
+                OpCode::Print(count) => {
+                    println!("{}", crate::join_values(&self.pop_values(count as usize))?);
+                }
+                OpCode::EPrint(count) => {
+                    eprintln!("{}", crate::join_values(&self.pop_values(count as usize))?);
+                }
+                OpCode::Interpolate(count) => {
+                    let mut result = String::new();
+                    for value in self.pop_values(count as usize) {
+                        result.push_str(&value.stringify()?);
+                    }
+                    self.stack.push(Value::String(result.into()));
+                }
+                OpCode::Array(count) => {
+                    let values = self.pop_values(count as usize);
+                    self.stack.push(Value::array(values));
+                }
+                OpCode::Jump(offset) => self.frame_mut().ip += offset as usize,
+                OpCode::JumpIfFalse(offset) => match self.pop() {
+                    Value::Boolean(true) => {}
+                    Value::Boolean(false) => self.frame_mut().ip += offset as usize,
+                    _ => {
+                        return Err(Error::new(
+                            self.line(),
+                            "if".to_string(),
+                            "Expect boolean condition".to_string(),
+                        ))
+                    }
+                },
+                OpCode::JumpUnlessTrue(offset) => {
+                    if self.pop() != Value::Boolean(true) {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                OpCode::JumpIfTrueOrPop(offset) => {
+                    if *self.peek(0) == Value::Boolean(true) {
+                        self.frame_mut().ip += offset as usize;
+                    } else {
+                        self.pop();
+                    }
+                }
+                OpCode::JumpIfFalseOrPop(offset) => {
+                    if *self.peek(0) == Value::Boolean(false) {
+                        self.frame_mut().ip += offset as usize;
+                    } else {
+                        self.pop();
+                    }
+                }
+                OpCode::JumpIfNotNilOrPop(offset) => {
+                    if *self.peek(0) != Value::Nil {
+                        self.frame_mut().ip += offset as usize;
+                    } else {
+                        self.pop();
+                    }
+                }
+                OpCode::JumpIfNil(offset) => {
+                    if *self.peek(0) == Value::Nil {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                OpCode::Loop(offset) => {
+                    self.step()?;
+                    self.frame_mut().ip -= offset as usize;
+                }
+                OpCode::Import(path) => {
+                    let namespace = crate::module::import(path.as_str(), self.line())?;
+                    self.stack.push(namespace);
+                }
+                OpCode::Iterable => {
+                    let value = self.pop();
+                    let token = self.in_token();
+                    let iterable =
+                        value.iterable(&token, &mut |method| self.call(method, Vec::new()))?;
+                    self.stack.push(iterable);
+                }
+                OpCode::ForNext(slot, offset) => {
+                    let index = self.frame().base + slot as usize;
+                    let mut position = match self.stack[index + 1] {
+                        Value::Number(position) => position as usize,
+                        _ => unreachable!(),
+                    };
+                    let iterable = self.stack[index].clone();
+                    let token = self.in_token();
+                    let element = match &iterable {
+                        Value::Generator(generator) => self.resume(generator)?,
+                        _ => iterable.next_element(&mut position, &token, &mut |method| {
+                            self.call(method, Vec::new())
+                        })?,
+                    };
+                    match element {
+                        Some(element) => {
+                            self.stack[index + 1] = Value::Number(position as f64);
+                            self.stack.push(element);
+                        }
+                        None => self.frame_mut().ip += offset as usize,
+                    }
+                }
+                OpCode::Call(argc) => {
+                    let callee = self.peek(argc as usize).clone();
+                    self.call_value(callee, argc as usize)?;
+                }
+                OpCode::TailCall(argc) => {
+                    let callee = self.peek(argc as usize).clone();
+                    if matches!(&callee, Value::Closure(closure, _) if !closure.function.is_generator)
+                    {
+                        // the callee and its arguments move down to the slots of this frame,
+                        // and its frame replaces this one
+                        let base = self.frame().base;
+                        if self.frame().closure.function.profile.is_some() {
+                            crate::profiler::exit();
+                        }
+                        self.close_upvalues(base);
+                        let start = self.stack.len() - argc as usize - 1;
+                        self.stack.drain(base..start);
+                        self.call_value(callee, argc as usize)?;
+                        let replaced = self.frames.len() - 2;
+                        self.frames.remove(replaced);
+                    } else {
+                        self.call_value(callee, argc as usize)?;
+                    }
+                }
+                OpCode::Closure(index) => {
+                    let function = self.frame().closure.function.functions[index as usize].clone();
+                    let mut upvalues = Vec::new();
+                    for (is_local, index) in &function.upvalues {
+                        if *is_local {
+                            let slot = self.frame().base + *index as usize;
+                            upvalues.push(self.capture_upvalue(slot));
+                        } else {
+                            upvalues.push(self.frame().closure.upvalues[*index as usize].clone());
+                        }
+                    }
+                    let globals = self.frame().closure.globals.clone();
+                    let closure = crate::gc::track(Rc::new(Closure {
+                        function,
+                        upvalues,
+                        globals,
+                    }));
+                    self.stack.push(Value::Closure(closure, None));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    if frame.closure.function.profile.is_some() {
+                        crate::profiler::exit();
+                    }
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.len() == depth {
+                        return Ok(result);
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Yield => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    if frame.closure.function.profile.is_some() {
+                        crate::profiler::exit();
+                    }
+                    // the variables captured from the frame are closed while it is suspended
+                    let mut upvalues = Vec::new();
+                    let stack = &self.stack;
+                    self.open_upvalues.retain(|upvalue| {
+                        let slot = match &*upvalue.borrow() {
+                            Upvalue::Open(slot) => *slot,
+                            Upvalue::Closed(_) => return false,
+                        };
+                        if slot >= frame.base {
+                            upvalues.push((slot - frame.base, upvalue.clone()));
+                            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+                            false
+                        } else {
+                            true
+                        }
+                    });
+                    let slots = self.stack.split_off(frame.base);
+                    let generator = self.generators.last().unwrap();
+                    generator.state.replace(GeneratorState::Vm {
+                        closure: frame.closure,
+                        ip: frame.ip,
+                        slots,
+                        upvalues,
+                    });
+                    return Ok(value);
+                }
+                OpCode::Class {
+                    name,
+                    methods,
+                    statics,
+                    has_super,
+                } => {
+                    let statics = self.pop_members(statics);
+                    let methods = self.pop_members(methods);
+                    let mut super_class = None;
+                    if has_super {
+                        if let Value::Class(class) = self.peek(0) {
+                            super_class = Some(class.clone());
+                        } else {
+                            return Err(self.name_error(name, "Superclass must be a class"));
+                        }
+                    }
+                    self.stack
+                        .push(Value::Class(crate::gc::track(Rc::new(LoxClass {
+                            name: name.to_string(),
+                            methods,
+                            statics: RefCell::new(statics),
+                            super_class,
+                        }))));
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, argc: usize) -> Result<(), Error> {
+        if callee.is_callable() {
+            if callee.is_variadic() && argc < callee.arity() {
+                return Err(Error::new(
+                    self.line(),
+                    ")".to_string(),
+                    format!(
+                        "Expected at least {} arguments but got {}.",
+                        callee.arity(),
+                        argc
+                    ),
+                ));
+            } else if !callee.is_variadic() && argc != callee.arity() {
+                return Err(Error::new(
+                    self.line(),
+                    ")".to_string(),
+                    format!("Expected {} arguments but got {}.", callee.arity(), argc),
+                ));
+            }
+        }
+        let base = self.stack.len() - argc - 1;
+        match callee {
+            Value::Closure(closure, this) => {
+                self.step()?;
+                if self.frames.len() >= unsafe { crate::limits::LIMITS.max_depth } {
+                    return Err(Error::new(
+                        self.line(),
+                        closure.function.name.clone(),
+                        "Stack overflow".to_string(),
+                    ));
+                }
+                if let Some(this) = this {
+                    self.stack[base] = Value::Instance(this);
+                }
+                if closure.function.is_generator {
+                    // the arguments wait in the slots of the generator until it is resumed
+                    let slots = self.stack.split_off(base);
+                    let generator = Generator::new(GeneratorState::Vm {
+                        closure,
+                        ip: 0,
+                        slots,
+                        upvalues: Vec::new(),
+                    });
+                    self.stack.push(Value::Generator(generator));
+                    return Ok(());
+                }
+                if let Some(id) = closure.function.profile {
+                    crate::profiler::enter(id);
+                }
+                self.frames.push(CallFrame {
+                    closure,
+                    ip: 0,
+                    base,
+                });
+                Ok(())
+            }
+            Value::Builtin(builtin) => {
+                let arguments = self.stack.drain(base + 1..).collect::<Vec<_>>();
+                self.pop();
+                let result = builtin.call(arguments)?;
+                self.stack.push(result);
+                Ok(())
+            }
+            Value::BuiltinMethod(builtin, this) => {
+                let arguments = std::iter::once(*this)
+                    .chain(self.stack.drain(base + 1..))
+                    .collect::<Vec<_>>();
+                self.pop();
+                let result = builtin.call(arguments)?;
+                self.stack.push(result);
+                Ok(())
+            }
+            Value::Instance(instance) => {
+                // `__call__`, found by `is_callable`
+                let method = instance.borrow().get(Symbol::CALL, instance.clone());
+                let method = method.ok_or_else(|| {
+                    Error::new(
+                        self.line(),
+                        ")".to_string(),
+                        "Can only call functions and classes".to_string(),
+                    )
+                })?;
+                self.stack[base] = method.clone();
+                self.call_value(method, argc)
+            }
+            Value::Class(class) => {
+                let initializer = class.methods.get(&Symbol::INIT).cloned();
+                let instance = crate::gc::track(Rc::new(RefCell::new(Instance {
+                    class,
+                    fields: HashMap::new(),
+                })));
+                if let Some(mut initializer) = initializer {
+                    initializer.bind(instance);
+                    self.stack[base] = initializer.clone();
+                    self.call_value(initializer, argc)
+                } else {
+                    self.stack[base] = Value::Instance(instance);
+                    Ok(())
+                }
+            }
+            _ => Err(Error::new(
+                self.line(),
+                ")".to_string(),
+                "Can only call functions and classes".to_string(),
+            )),
+        }
+    }
+
+    /// Push the value of a property, or call it if it is a getter
+    fn push_property(&mut self, value: Value) -> Result<(), Error> {
+        self.stack.push(value.clone());
+        if value.is_getter() {
+            self.call_value(value, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Pop `count` pairs of a name and a value
+    fn pop_members(&mut self, count: u8) -> HashMap<Symbol, Value> {
+        self.pop_values(count as usize * 2)
+            .chunks(2)
+            .map(|pair| (Symbol::intern(&pair[0].to_string()), pair[1].clone()))
+            .collect()
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in &self.open_upvalues {
+            if let Upvalue::Open(open) = &*upvalue.borrow() {
+                if *open == slot {
+                    return upvalue.clone();
+                }
+            }
+        }
+        let upvalue = crate::gc::track(Rc::new(RefCell::new(Upvalue::Open(slot))));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Move the values of the slots from `last` upward off the stack
+    fn close_upvalues(&mut self, last: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match &*upvalue.borrow() {
+                Upvalue::Open(slot) => *slot,
+                Upvalue::Closed(_) => return false,
+            };
+            if slot >= last {
+                *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn get_index(&self, array: Value, index: Value) -> Result<Value, Error> {
+        if let Some(value) = array.call_special(Symbol::INDEX, vec![index.clone()]) {
+            return value;
+        }
+        let index = match index {
+            Value::Number(index) => index,
+            _ => return Err(self.operator_error("]", "Index must be a number")),
+        };
+        if index.is_nan() || index.is_infinite() || index < 0.0 {
+            return Err(self.operator_error("]", "Index must be a non-negative integer"));
+        }
+        match array {
+            Value::Array(array) => match array.borrow().get(index as usize) {
+                Some(value) => Ok(value.clone()),
+                None => Err(self.operator_error("]", "Index out of bounds")),
+            },
+            Value::String(s) => match s.chars().nth(index as usize) {
+                Some(c) => Ok(Value::String(c.to_string().into())),
+                None => Err(self.operator_error("]", "Index out of bounds")),
+            },
+            _ => Err(self.operator_error("]", "Can't index non-array or non-string value")),
+        }
+    }
+
+    /// Pop `count` values, in the order they were pushed
+    fn pop_values(&mut self, count: usize) -> Vec<Value> {
+        let start = self.stack.len() - count;
+        self.stack.drain(start..).collect()
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// The line of the instruction being executed, 0 before the script is called
+    fn line(&self) -> usize {
+        self.frames.last().map_or(0, |frame| {
+            frame.closure.function.chunk.get_line(frame.ip - 1)
+        })
+    }
+
+    /// Check for an interrupt and count a step of the limits
+    fn step(&self) -> Result<(), Error> {
+        crate::interrupt::check()
+            .and_then(|_| crate::limits::step())
+            .map_err(|mut e| {
+                e.line = self.line();
+                e
+            })
+    }
+
+    /// Make a token of the name, for errors
+    fn name_token(&self, name: Symbol) -> Token {
+        Token {
+            token_type: TokenType::Identifier(name),
+            lexeme: name.to_string(),
+            line: self.line(),
+            column: 0,
+        }
+    }
+
+    /// The `in` of the `for in` loop being executed, for its errors
+    fn in_token(&self) -> Token {
+        Token {
+            token_type: TokenType::In,
+            lexeme: "in".to_string(),
+            line: self.line(),
+            column: 0,
+        }
+    }
+
+    fn name_error(&self, name: Symbol, message: &str) -> Error {
+        Error::new(self.line(), name.to_string(), message.to_string())
+    }
+
+    fn operator_error(&self, operator: &str, message: &str) -> Error {
+        Error::new(self.line(), operator.to_string(), message.to_string())
+    }
+}
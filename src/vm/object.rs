@@ -0,0 +1,91 @@
+//! Runtime objects of the vm
+use crate::gc::{trace_value, Trace};
+use crate::profiler::FunctionId;
+use crate::vm::Chunk;
+use crate::{Environment, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A compiled function, shared by all the closures created from it
+#[derive(Debug)]
+pub struct FunctionProto {
+    pub name: String,
+    pub params: Vec<String>,
+    pub chunk: Chunk,
+    /// The functions declared in the body, created by `OpCode::Closure`
+    pub functions: Vec<Rc<FunctionProto>>,
+    /// `(is_local, index)` of each captured variable, local to the enclosing function or one
+    /// of its upvalues
+    pub upvalues: Vec<(bool, u8)>,
+    pub is_initializer: bool,
+    pub is_getter: bool,
+    pub is_generator: bool,
+    /// `None` for the script
+    pub profile: Option<FunctionId>,
+}
+
+impl FunctionProto {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
+
+impl std::fmt::Display for FunctionProto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut params = String::new();
+        for param in &self.params {
+            params.push_str(&format!("{} ", param));
+        }
+        write!(f, "<fn>({} {})", self.name, params)
+    }
+}
+
+/// A variable captured by a closure, it stays on the stack while its frame lives
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// The global environment of the script or module the function is declared in
+    pub globals: Rc<RefCell<Environment>>,
+}
+
+impl std::fmt::Debug for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+impl Trace for RefCell<Upvalue> {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        let Ok(upvalue) = self.try_borrow() else {
+            return false;
+        };
+        if let Upvalue::Closed(value) = &*upvalue {
+            trace_value(value, visit);
+        }
+        true
+    }
+
+    fn clear(&self) {
+        if let Ok(mut upvalue) = self.try_borrow_mut() {
+            if let Upvalue::Closed(value) = &mut *upvalue {
+                *value = Value::Nil;
+            }
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        for upvalue in &self.upvalues {
+            visit(Rc::as_ptr(upvalue) as *const ());
+        }
+        visit(Rc::as_ptr(&self.globals) as *const ());
+        true
+    }
+}
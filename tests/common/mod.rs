@@ -0,0 +1,39 @@
+//! Run scripts with the rlox binary. Each run is its own process, the interpreter keeps its
+//! state in globals that tests running in parallel threads would share.
+#![allow(dead_code)]
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const ENGINES: [&str; 2] = ["tree", "vm"];
+
+/// Run the script at `path` with the engine, return what it printed on stdout then stderr
+pub fn run_file(engine: &str, path: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(["--engine", engine])
+        .arg(path)
+        .output()
+        .expect("Could not run rlox");
+    let mut printed = String::from_utf8_lossy(&output.stdout).to_string();
+    printed.push_str(&String::from_utf8_lossy(&output.stderr));
+    printed
+}
+
+/// Run `source` from a temporary file
+pub fn run(engine: &str, source: &str) -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "rlox-test-{}-{}.lox",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, source).expect("Could not write the script");
+    let printed = run_file(engine, &path);
+    let _ = std::fs::remove_file(&path);
+    printed
+}
+
+/// The lines printed by `source`, with the engine
+pub fn lines(engine: &str, source: &str) -> Vec<String> {
+    run(engine, source).lines().map(str::to_string).collect()
+}
@@ -0,0 +1,33 @@
+//! Every script of `tests/corpus` prints what its `.out` file holds on both engines, errors
+//! included
+mod common;
+
+use common::{run_file, ENGINES};
+use std::path::Path;
+
+#[test]
+fn engines_print_the_expected_output_of_the_corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut scripts = std::fs::read_dir(&corpus)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+        .collect::<Vec<_>>();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+    let mut differences = Vec::new();
+    for script in &scripts {
+        let expected = std::fs::read_to_string(script.with_extension("out"))
+            .unwrap_or_else(|_| panic!("{} has no .out file", script.display()));
+        for engine in ENGINES {
+            let printed = run_file(engine, script);
+            if printed != expected {
+                differences.push(format!(
+                    "{} on {engine}\n--- expected\n{expected}--- printed\n{printed}",
+                    script.display()
+                ));
+            }
+        }
+    }
+    assert!(differences.is_empty(), "{}", differences.join("\n"));
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::{lines, ENGINES};
+
+#[test]
+fn collects_instance_and_bound_method_cycles() {
+    let source = "
+        class Node { init() { this.callback = this.run; } run() { return 1; } }
+        gc();
+        fun make() { var node = Node(); }
+        for (var i = 0; i < 10; i = i + 1) make();
+        print gc();
+        print gc();
+    ";
+    // the tree engine also tracks the environment binding `this`
+    assert_eq!(lines("tree", source), ["20", "0"]);
+    assert_eq!(lines("vm", source), ["10", "0"]);
+}
+
+#[test]
+fn array_holding_itself_is_a_copy_not_a_cycle() {
+    let source = "
+        class Node {}
+        gc();
+        fun make() {
+            var a = [1, 2];
+            a[0] = a;
+            var node = Node();
+            node.items = [node];
+        }
+        make();
+        print gc();
+        var b = [1];
+        b[0] = b;
+        print len(b[0]);
+        print gc();
+    ";
+    for engine in ENGINES {
+        // only the instance and the array holding it
+        assert_eq!(lines(engine, source), ["2", "1", "0"], "{engine}");
+    }
+}
+
+#[test]
+fn collects_closure_and_environment_cycles() {
+    let source = "
+        gc();
+        fun counter() {
+            var count = 0;
+            fun increment() { count = count + 1; return increment; }
+            return nil;
+        }
+        for (var i = 0; i < 5; i = i + 1) counter();
+        print gc();
+        print gc();
+    ";
+    // an environment per call on the tree engine, a closure and two upvalues on the vm
+    assert_eq!(lines("tree", source), ["5", "0"]);
+    assert_eq!(lines("vm", source), ["15", "0"]);
+}
+
+#[test]
+fn memory_counts_objects_and_collections() {
+    let source = "
+        class Node {}
+        gc();
+        var before = memory().objects;
+        fun make() { var a = Node(); var b = Node(); a.other = b; b.other = a; }
+        for (var i = 0; i < 100; i = i + 1) make();
+        print memory().objects - before >= 200;
+        print gc();
+        var after = memory();
+        print after.objects - before <= 2;
+        print after.collections;
+        print after.freed;
+    ";
+    for engine in ENGINES {
+        assert_eq!(
+            lines(engine, source),
+            ["true", "200", "true", "2", "200"],
+            "{engine}"
+        );
+    }
+}
+
+#[test]
+fn live_cycles_survive_collections_on_threshold() {
+    let source = "
+        class Node {}
+        var live = Node();
+        live.self = live;
+        live.items = [live];
+        fun make() { var garbage = Node(); garbage.self = garbage; }
+        for (var i = 0; i < 30000; i = i + 1) make();
+        var stats = memory();
+        print stats.collections > 0;
+        print stats.freed > 0;
+        print live.self == live;
+        print live.items[0] == live;
+    ";
+    for engine in ENGINES {
+        assert_eq!(
+            lines(engine, source),
+            ["true", "true", "true", "true"],
+            "{engine}"
+        );
+    }
+}
+
+#[test]
+fn collections_keep_up_with_many_live_objects() {
+    // every object stays alive, collecting again on each new one would take minutes
+    let source = "
+        class Node {}
+        var head = nil;
+        for (var i = 0; i < 50000; i = i + 1) {
+            var node = Node();
+            node.next = head;
+            head = node;
+        }
+        var count = 0;
+        for (var node = head; node != nil; node = node.next) count = count + 1;
+        print count;
+        print memory().collections < 10;
+    ";
+    for engine in ENGINES {
+        assert_eq!(lines(engine, source), ["50000", "true"], "{engine}");
+    }
+}
+
+#[test]
+fn module_held_by_a_collected_cycle_stays_loaded() {
+    let directory = std::env::temp_dir().join(format!("rlox-gc-module-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(
+        directory.join("gm.lox"),
+        "fun helper(x) { return x; } var data = 7;",
+    )
+    .unwrap();
+    std::fs::write(directory.join("bx.lox"), "class Box {}").unwrap();
+    let main = directory.join("main.lox");
+    // the copies of the namespace share the one reference to the module environment
+    std::fs::write(
+        &main,
+        "
+        import \"gm.lox\" as u;
+        import \"bx.lox\" as bx;
+        var b = bx.Box();
+        b.me = b;
+        b.m1 = u;
+        b.m2 = u;
+        b.m3 = u;
+        b = nil;
+        print gc();
+        print u.data;
+        print u.helper(3);
+        ",
+    )
+    .unwrap();
+    for engine in ENGINES {
+        assert_eq!(
+            common::run_file(engine, &main).lines().collect::<Vec<_>>(),
+            ["1", "7", "3"],
+            "{engine}"
+        );
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+}
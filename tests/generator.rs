@@ -0,0 +1,57 @@
+mod common;
+
+use common::{lines, ENGINES};
+
+#[test]
+fn next_returns_nil_once_exhausted() {
+    let source = "
+        fun* count(n) { for (var i = 0; i < n; i = i + 1) yield i; }
+        var counter = count(2);
+        print counter.next();
+        print counter.next();
+        print counter.next();
+        print counter.next();
+        print type(counter.next);
+    ";
+    for engine in ENGINES {
+        assert_eq!(
+            lines(engine, source),
+            ["0", "1", "Nil", "Nil", "function"],
+            "{engine}"
+        );
+    }
+}
+
+#[test]
+fn methods_can_be_generators() {
+    let source = "
+        class Bag {
+            init(items) { this.items = items; }
+            *each() { for (var item in this.items) yield item * 10; }
+            class *upto(n) { for (var i = 0; i < n; i = i + 1) yield i; }
+        }
+        var bag = Bag([1, 2]);
+        for (var x in bag.each()) print x;
+        var each = bag.each();
+        print each.next();
+        for (var x in Bag.upto(2)) print x;
+    ";
+    for engine in ENGINES {
+        assert_eq!(
+            lines(engine, source),
+            ["10", "20", "10", "0", "1"],
+            "{engine}"
+        );
+    }
+}
+
+#[test]
+fn initializer_cant_be_a_generator() {
+    for engine in ENGINES {
+        assert_eq!(
+            lines(engine, "class Bag { *init() {} }"),
+            ["[line 1:14] Error at 'init', message: An initializer can't be a generator"],
+            "{engine}"
+        );
+    }
+}
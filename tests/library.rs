@@ -0,0 +1,193 @@
+//! The interpreter as a library. It keeps its state in globals, so the tests take turns.
+use rlox::{Engine, HashKey, Limits, Value};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn lock(engine: Engine) -> MutexGuard<'static, ()> {
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    unsafe {
+        rlox::ENGINE = engine;
+    }
+    rlox::set_limits(Limits::DEFAULT);
+    rlox::set_sandbox(false);
+    guard
+}
+
+#[test]
+fn runs_share_the_global_environment() {
+    for engine in [Engine::Tree, Engine::Vm] {
+        let _guard = lock(engine);
+        rlox::run("var shared = 41;").unwrap();
+        rlox::run("shared = shared + 1;").unwrap();
+        rlox::run("if (shared != 42) missing();").unwrap();
+    }
+}
+
+#[test]
+fn errors_are_returned() {
+    for engine in [Engine::Tree, Engine::Vm] {
+        let _guard = lock(engine);
+        let error = rlox::run("print 1 +;").unwrap_err();
+        assert_eq!(error.line, 1);
+        let error = rlox::run("undefined_function();").unwrap_err();
+        assert_eq!(error.message, "Undefined variable");
+    }
+}
+
+#[test]
+fn interrupt_handle_stops_a_run_from_another_thread() {
+    for engine in [Engine::Tree, Engine::Vm] {
+        let _guard = lock(engine);
+        let handle = rlox::interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            handle.interrupt();
+        });
+        let error = rlox::run("while (true) {}").unwrap_err();
+        interrupter.join().unwrap();
+        assert_eq!(error.message, "Interrupted");
+        // the next run isn't interrupted
+        rlox::run("var after_interrupt = 1;").unwrap();
+    }
+}
+
+#[test]
+fn step_limit_stops_a_run() {
+    for engine in [Engine::Tree, Engine::Vm] {
+        let _guard = lock(engine);
+        rlox::set_limits(Limits {
+            max_steps: Some(1000),
+            ..Limits::DEFAULT
+        });
+        let error = rlox::run("while (true) {}").unwrap_err();
+        assert_eq!(error.message, "Step limit exceeded");
+        // the steps are counted again for each run
+        rlox::run("for (var i = 0; i < 500; i = i + 1) {}").unwrap();
+        // the vm counts calling the script as a step, before it has a line
+        rlox::set_limits(Limits {
+            max_steps: Some(0),
+            ..Limits::DEFAULT
+        });
+        let error = rlox::run("while (true) {}").unwrap_err();
+        assert_eq!(error.message, "Step limit exceeded");
+    }
+}
+
+#[test]
+fn depth_limit_stops_a_script_file() {
+    let script = std::env::temp_dir().join(format!("rlox-depth-{}.lox", std::process::id()));
+    std::fs::write(
+        &script,
+        "fun down(n) { return 1 + down(n + 1); }\ndown(0);\n",
+    )
+    .unwrap();
+    for engine in [Engine::Tree, Engine::Vm] {
+        let _guard = lock(engine);
+        rlox::set_limits(Limits {
+            max_depth: 100,
+            ..Limits::DEFAULT
+        });
+        let path = script.to_string_lossy().to_string();
+        let error = rlox::run_on_interpreter_thread(move || rlox::run_file(&path)).unwrap_err();
+        assert_eq!(error.message, "Stack overflow");
+    }
+    std::fs::remove_file(&script).unwrap();
+}
+
+#[test]
+fn default_depth_limit_fits_the_stack_of_any_thread() {
+    for engine in [Engine::Tree, Engine::Vm] {
+        let _guard = lock(engine);
+        // a spawned thread has a small stack, the run moves to the interpreter thread
+        let error = std::thread::spawn(|| {
+            rlox::run("fun f(n) { return f(n + 1) + 1; } f(0);").map_err(|e| e.message)
+        })
+        .join()
+        .unwrap()
+        .unwrap_err();
+        assert_eq!(error, "Stack overflow");
+        let error = rlox::run("fun g(n) { return g(n + 1) + 1; } g(0);").unwrap_err();
+        assert_eq!(error.message, "Stack overflow");
+    }
+}
+
+#[test]
+fn heap_limit_needs_the_counting_allocator() {
+    for engine in [Engine::Tree, Engine::Vm] {
+        let _guard = lock(engine);
+        // the tests don't install it, so the heap size is unknown
+        rlox::run("if (memory().heap != nil) missing();").unwrap();
+        rlox::set_limits(Limits {
+            max_heap: Some(1 << 30),
+            ..Limits::DEFAULT
+        });
+        let error = rlox::run("while (true) {}").unwrap_err();
+        assert_eq!(
+            error.message,
+            "Memory limit unavailable without the Counting allocator"
+        );
+    }
+}
+
+#[test]
+fn script_file_can_be_imported_by_the_next_one() {
+    let directory = std::env::temp_dir().join(format!("rlox-scripts-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("a.lox"), "var from_a = 1;").unwrap();
+    std::fs::write(directory.join("b.lox"), "import \"a.lox\" as a;").unwrap();
+    let _guard = lock(Engine::Tree);
+    let path = |name: &str| directory.join(name).to_string_lossy().to_string();
+    rlox::run_file(&path("a.lox")).unwrap();
+    rlox::run_file(&path("b.lox")).unwrap();
+    // the directory of the scripts is gone too
+    let error = rlox::run("import \"a.lox\" as a;").unwrap_err();
+    assert_eq!(error.message, "Cannot find module");
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn missing_script_file_is_an_error() {
+    let _guard = lock(Engine::Tree);
+    let error = rlox::run_file("no/such/script.lox").unwrap_err();
+    assert_eq!(error.loc, "no/such/script.lox");
+}
+
+#[test]
+fn sandbox_applies_to_the_following_runs() {
+    for engine in [Engine::Tree, Engine::Vm] {
+        let _guard = lock(engine);
+        rlox::run("read_file;").unwrap();
+        rlox::set_sandbox(true);
+        let error = rlox::run("read_file;").unwrap_err();
+        assert_eq!(error.message, "Undefined variable");
+        // not a constant anymore, the script may use the name
+        rlox::run("var read_file = 1;").unwrap();
+        rlox::set_sandbox(false);
+        rlox::run("read_file(\"Cargo.toml\");").unwrap();
+    }
+}
+
+#[test]
+// a key hashes the address of a heap value, not what is behind the `RefCell`
+#[allow(clippy::mutable_key_type)]
+fn hash_keys_reject_nan_and_arrays() {
+    let _guard = lock(Engine::Tree);
+    let mut map = HashMap::new();
+    map.insert(HashKey::new(Value::Number(0.0)).unwrap(), 1);
+    map.insert(HashKey::new(Value::String("a".into())).unwrap(), 2);
+    assert_eq!(
+        map.get(&HashKey::new(Value::Number(-0.0)).unwrap()),
+        Some(&1)
+    );
+    assert_eq!(
+        map.get(&HashKey::new(Value::String("a".into())).unwrap()),
+        Some(&2)
+    );
+    let error = HashKey::new(Value::Number(f64::NAN)).unwrap_err();
+    assert_eq!(error.message, "NaN can't be a key");
+    let error = HashKey::new(Value::array(vec![Value::Number(1.0)])).unwrap_err();
+    assert_eq!(error.message, "An array can't be a key");
+}
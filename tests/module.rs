@@ -0,0 +1,50 @@
+mod common;
+
+use common::{run_file, ENGINES};
+
+#[test]
+fn namespace_is_a_live_view_of_the_module() {
+    let directory = std::env::temp_dir().join(format!("rlox-module-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(
+        directory.join("counter.lox"),
+        "
+        const step = 1;
+        var count = 0;
+        var history = [0, 0];
+        fun bump() { count = count + step; return count; }
+        fun get() { return count; }
+        fun first() { return history[0]; }
+        ",
+    )
+    .unwrap();
+    let main = directory.join("main.lox");
+    std::fs::write(
+        &main,
+        "
+        import \"counter.lox\" as counter;
+        counter.bump();
+        print counter.count == counter.get();
+        counter.count = 10;
+        print counter.get();
+        counter.history[0] = 5;
+        print counter.first();
+        print fields(counter);
+        print counter;
+        counter.step = 2;
+        ",
+    )
+    .unwrap();
+    for engine in ENGINES {
+        assert_eq!(
+            run_file(engine, &main),
+            "true\n10\n5\n\
+             [String(\"bump\"), String(\"count\"), String(\"first\"), String(\"get\"), \
+             String(\"history\"), String(\"step\")]\n\
+             <module counter>\n\
+             [line 11] Error step, message: Can't assign to a constant\n",
+            "{engine}"
+        );
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+}
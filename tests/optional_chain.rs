@@ -0,0 +1,55 @@
+mod common;
+
+use common::{lines, ENGINES};
+
+#[test]
+fn nil_skips_the_rest_of_the_chain() {
+    let source = "
+        class Box { init() { this.empty = nil; this.items = [1, 2]; } me() { return this; } }
+        var none = nil;
+        var box = Box();
+        print none?.a.b;
+        print none?.me();
+        print none?.a[0];
+        print none?.me().a.b(1, 2);
+        print box?.me().items[1];
+        print box?.empty ?? \"default\";
+    ";
+    for engine in ENGINES {
+        assert_eq!(
+            lines(engine, source),
+            ["Nil", "Nil", "Nil", "Nil", "2", "default"],
+            "{engine}"
+        );
+    }
+}
+
+#[test]
+fn chain_ends_at_parentheses_and_at_plain_links_after_a_value() {
+    for engine in ENGINES {
+        assert_eq!(
+            lines(engine, "var none = nil;\nprint (none?.a).b;"),
+            ["[line 2] Error b, message: Only instance have properties"],
+            "{engine}"
+        );
+        assert_eq!(
+            lines(
+                engine,
+                "class Box { init() { this.empty = nil; } }\nprint Box()?.empty.a;"
+            ),
+            ["[line 2] Error a, message: Only instance have properties"],
+            "{engine}"
+        );
+    }
+}
+
+#[test]
+fn chain_is_not_an_assignment_target() {
+    for engine in ENGINES {
+        assert_eq!(
+            lines(engine, "var none = nil;\nnone?.a.b = 1;"),
+            ["[line 2:11] Error at '=', message: Invalid assignment target"],
+            "{engine}"
+        );
+    }
+}
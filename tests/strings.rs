@@ -0,0 +1,54 @@
+mod common;
+
+use common::{lines, ENGINES};
+
+#[test]
+fn len_counts_characters() {
+    let source = "
+        print len(\"héllo\");
+        print len(\"日本語\");
+        print len(\"a👋b\");
+        print len(\"\");
+    ";
+    for engine in ENGINES {
+        assert_eq!(lines(engine, source), ["5", "3", "3", "0"], "{engine}");
+    }
+}
+
+#[test]
+fn indexing_by_character() {
+    let source = "
+        var text = \"日本語👋\";
+        print text[0];
+        print text[2];
+        print text[3];
+        print text[4];
+    ";
+    for engine in ENGINES {
+        assert_eq!(
+            lines(engine, source),
+            [
+                "日",
+                "語",
+                "👋",
+                "[line 6] Error ], message: Index out of bounds"
+            ],
+            "{engine}"
+        );
+    }
+}
+
+#[test]
+fn slice_by_character() {
+    let source = "
+        print slice(\"日本語\", 1, 3);
+        print slice(\"a👋b\", 1, 2);
+        print slice(\"héllo\", 0, 0) == \"\";
+        print slice(\"日本\", 1, 3);
+    ";
+    for engine in ENGINES {
+        let printed = lines(engine, source);
+        assert_eq!(printed[..3], ["本語", "👋", "true"], "{engine}");
+        assert!(printed[3].ends_with("Slice out of bounds"), "{engine}");
+    }
+}